@@ -0,0 +1,274 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+
+use crate::constants;
+
+/// File extension of compiled artifacts stored in the [`CompileCache`].
+const COMPILED_EXTENSION: &str = "cwasm";
+/// File extension of artifacts being written to the [`CompileCache`].
+const TMP_EXTENSION: &str = "tmp";
+/// Age after which an artifact being written is considered abandoned, e.g. by a
+/// shim that was killed while writing it.
+const STALE_TMP_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// A node-wide, on-disk cache of components precompiled by the shim.
+///
+/// Entries are keyed by the digest of the source Wasm layer and the
+/// precompilation compatibility hash of the Wasmtime engine that produced them,
+/// so artifacts built by a different engine configuration are never reused.
+/// The total size of the cache is bounded; the least recently used entries are
+/// evicted first.
+///
+/// The cache directory must only be writable by the shim, as the artifacts it
+/// contains are later loaded as trusted, AOT compiled code.
+pub(crate) struct CompileCache {
+    dir: PathBuf,
+    compatibility_hash: String,
+    max_size_bytes: u64,
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    size: u64,
+    last_used: SystemTime,
+}
+
+impl CompileCache {
+    pub(crate) fn new(
+        dir: impl Into<PathBuf>,
+        compatibility_hash: impl Into<String>,
+        max_size_bytes: u64,
+    ) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create precompile cache dir {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            compatibility_hash: compatibility_hash.into(),
+            max_size_bytes,
+        })
+    }
+
+    /// Opens the cache at `dir`, bounded by the size configured in the environment.
+    pub(crate) fn open(dir: impl Into<PathBuf>, compatibility_hash: &str) -> Result<Self> {
+        let max_size_bytes = match env::var(constants::SPIN_PRECOMPILE_CACHE_MAX_SIZE_ENV) {
+            Ok(size) => size.trim().parse::<u64>().with_context(|| {
+                format!(
+                    "invalid value {size:?} for {}: expected a size in bytes",
+                    constants::SPIN_PRECOMPILE_CACHE_MAX_SIZE_ENV
+                )
+            })?,
+            Err(_) => constants::SPIN_PRECOMPILE_CACHE_MAX_SIZE_DEFAULT,
+        };
+        Self::new(dir, compatibility_hash, max_size_bytes)
+    }
+
+    /// Opens the cache configured by the shim's environment, if any.
+    pub(crate) fn from_env(compatibility_hash: &str) -> Result<Option<Self>> {
+        env::var_os(constants::SPIN_PRECOMPILE_CACHE_DIR_ENV)
+            .map(|dir| Self::open(dir, compatibility_hash))
+            .transpose()
+    }
+
+    /// Returns the compiled artifact for the layer with the given digest, marking
+    /// it as recently used.
+    pub(crate) fn get(&self, digest: &str) -> Option<Vec<u8>> {
+        let path = self.entry_path(digest);
+        let bytes = fs::read(&path).ok()?;
+        if let Err(err) = File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(SystemTime::now()))
+        {
+            debug!("failed to update last use of {}: {err}", path.display());
+        }
+        Some(bytes)
+    }
+
+    /// Stores the compiled artifact for the layer with the given digest and
+    /// evicts entries as needed to stay within the configured size.
+    pub(crate) fn put(&self, digest: &str, compiled: &[u8]) -> Result<()> {
+        let path = self.entry_path(digest);
+        // Write to a temporary file first so that concurrent shims never observe a
+        // partially written artifact.
+        let tmp_path = path.with_extension(format!("{}.{TMP_EXTENSION}", std::process::id()));
+        File::create(&tmp_path)
+            .and_then(|mut f| f.write_all(compiled))
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("failed to move compiled artifact to {}", path.display()))?;
+        self.gc()
+    }
+
+    /// Removes a cached artifact, e.g. because it could not be loaded.
+    pub(crate) fn remove(&self, digest: &str) {
+        let _ = fs::remove_file(self.entry_path(digest));
+    }
+
+    /// Removes abandoned temporary files and artifacts produced by other engine
+    /// configurations, and evicts the least recently used artifacts until the
+    /// cache fits within its size limit.
+    pub(crate) fn gc(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        for dir_entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("failed to read {}", self.dir.display()))?
+        {
+            let dir_entry = dir_entry?;
+            let path = dir_entry.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(COMPILED_EXTENSION) => {}
+                Some(TMP_EXTENSION) => {
+                    let age = dir_entry
+                        .metadata()?
+                        .modified()?
+                        .elapsed()
+                        .unwrap_or_default();
+                    if age > STALE_TMP_AGE {
+                        info!("removing abandoned temporary file {}", path.display());
+                        let _ = fs::remove_file(&path);
+                    }
+                    continue;
+                }
+                _ => continue,
+            }
+            if !self.is_compatible(&path) {
+                info!(
+                    "removing incompatible precompiled artifact {}",
+                    path.display()
+                );
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            let metadata = dir_entry.metadata()?;
+            entries.push(CacheEntry {
+                path,
+                size: metadata.len(),
+                last_used: metadata.modified()?,
+            });
+        }
+
+        let mut total_size: u64 = entries.iter().map(|e| e.size).sum();
+        entries.sort_by_key(|e| e.last_used);
+        for entry in entries {
+            if total_size <= self.max_size_bytes {
+                break;
+            }
+            info!(
+                "evicting precompiled artifact {} ({} bytes)",
+                entry.path.display(),
+                entry.size
+            );
+            match fs::remove_file(&entry.path) {
+                Ok(()) => total_size -= entry.size,
+                Err(err) => warn!("failed to evict {}: {err}", entry.path.display()),
+            }
+        }
+        Ok(())
+    }
+
+    fn entry_path(&self, digest: &str) -> PathBuf {
+        // Digests are of the form `<algorithm>:<encoded>`, which is not a valid file
+        // name on all platforms.
+        self.dir.join(format!(
+            "{}.{}.{COMPILED_EXTENSION}",
+            digest.replace(':', "_"),
+            self.compatibility_hash
+        ))
+    }
+
+    fn is_compatible(&self, path: &Path) -> bool {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.rsplit_once('.'))
+            .is_some_and(|(_, hash)| hash == self.compatibility_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST_A: &str =
+        "sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b";
+    const DIGEST_B: &str =
+        "sha256:1f09d30c707d53f3d16c530dd73d70a6ce7596a9b5ba2d2b5e5f2e2f2b1e6f0a";
+
+    fn set_last_used(cache: &CompileCache, digest: &str, time: SystemTime) {
+        File::options()
+            .write(true)
+            .open(cache.entry_path(digest))
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn put_and_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CompileCache::new(dir.path(), "1234", 1024).unwrap();
+        assert!(cache.get(DIGEST_A).is_none());
+        cache.put(DIGEST_A, b"compiled").unwrap();
+        assert_eq!(cache.get(DIGEST_A).unwrap(), b"compiled");
+
+        // Artifacts from another engine configuration are not visible
+        let other = CompileCache::new(dir.path(), "5678", 1024).unwrap();
+        assert!(other.get(DIGEST_A).is_none());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CompileCache::new(dir.path(), "1234", 10).unwrap();
+        cache.put(DIGEST_A, b"aaaaaa").unwrap();
+        cache.put(DIGEST_B, b"bbbb").unwrap();
+        let now = SystemTime::now();
+        set_last_used(&cache, DIGEST_A, now);
+        set_last_used(&cache, DIGEST_B, now - Duration::from_secs(60));
+
+        cache.put("sha256:c", b"c").unwrap();
+        assert!(cache.get(DIGEST_A).is_some());
+        assert!(cache.get(DIGEST_B).is_none());
+        assert!(cache.get("sha256:c").is_some());
+    }
+
+    #[test]
+    fn gc_removes_incompatible_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = CompileCache::new(dir.path(), "1234", 1024).unwrap();
+        old.put(DIGEST_A, b"compiled").unwrap();
+
+        let cache = CompileCache::new(dir.path(), "5678", 1024).unwrap();
+        cache.gc().unwrap();
+        assert!(!old.entry_path(DIGEST_A).exists());
+    }
+
+    #[test]
+    fn gc_removes_abandoned_temporary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CompileCache::new(dir.path(), "1234", 1024).unwrap();
+        let tmp_path = |pid: u32| {
+            cache
+                .entry_path(DIGEST_A)
+                .with_extension(format!("{pid}.{TMP_EXTENSION}"))
+        };
+        let abandoned = tmp_path(1);
+        let in_progress = tmp_path(2);
+        File::create(&abandoned)
+            .unwrap()
+            .set_modified(SystemTime::now() - STALE_TMP_AGE * 2)
+            .unwrap();
+        File::create(&in_progress).unwrap();
+
+        cache.gc().unwrap();
+        assert!(!abandoned.exists());
+        assert!(in_progress.exists());
+    }
+}
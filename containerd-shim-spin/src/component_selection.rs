@@ -0,0 +1,231 @@
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Context, Result};
+use glob::Pattern;
+use spin_app::locked::{LockedApp, LockedTrigger};
+
+/// Prefix of selectors matching the components of HTTP triggers by route prefix
+const ROUTE_SELECTOR_PREFIX: &str = "route:";
+/// Prefix of selectors matching the components of Redis triggers by channel and of
+/// MQTT triggers by topic
+const CHANNEL_SELECTOR_PREFIX: &str = "channel:";
+/// Prefix of selectors excluding the components they match
+const EXCLUSION_PREFIX: char = '!';
+
+/// Selects components of an application, as documented on
+/// [`crate::constants::SPIN_COMPONENTS_TO_RETAIN_ENV`].
+enum Selector {
+    /// Components whose id matches the glob pattern
+    Id(Pattern),
+    /// Components of HTTP triggers whose route starts with the path prefix
+    Route(String),
+    /// Components of Redis or MQTT triggers whose channel or topic matches the glob pattern
+    Channel(Pattern),
+}
+
+impl Selector {
+    fn parse(selector: &str, origin: &str) -> Result<Self> {
+        let pattern = |pattern: &str| {
+            Pattern::new(pattern)
+                .with_context(|| format!("invalid pattern {pattern:?} in {origin}"))
+        };
+        if let Some(prefix) = selector.strip_prefix(ROUTE_SELECTOR_PREFIX) {
+            Ok(Self::Route(prefix.trim_end_matches('/').to_string()))
+        } else if let Some(channel) = selector.strip_prefix(CHANNEL_SELECTOR_PREFIX) {
+            Ok(Self::Channel(pattern(channel)?))
+        } else {
+            Ok(Self::Id(pattern(selector)?))
+        }
+    }
+
+    fn matches(&self, component_id: &str, triggers: &[&LockedTrigger]) -> bool {
+        match self {
+            Self::Id(pattern) => pattern.matches(component_id),
+            Self::Route(prefix) => triggers
+                .iter()
+                .filter(|trigger| trigger.trigger_type == "http")
+                .filter_map(|trigger| trigger.trigger_config.get("route")?.as_str())
+                .any(|route| route_has_prefix(route, prefix)),
+            Self::Channel(pattern) => triggers
+                .iter()
+                .filter_map(|trigger| match trigger.trigger_type.as_str() {
+                    "redis" => trigger.trigger_config.get("channel")?.as_str(),
+                    "mqtt" => trigger.trigger_config.get("topic")?.as_str(),
+                    _ => None,
+                })
+                .any(|channel| pattern.matches(channel)),
+        }
+    }
+}
+
+/// Returns whether the HTTP route, e.g. `/api/...`, lies under the path prefix,
+/// comparing whole path segments.
+fn route_has_prefix(route: &str, prefix: &str) -> bool {
+    let route = route.trim_end_matches("/...").trim_end_matches('/');
+    prefix.is_empty()
+        || route == prefix
+        || route
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Resolves selectors in the format of [`crate::constants::SPIN_COMPONENTS_TO_RETAIN_ENV`],
+/// configured by `origin`, to the ids of the components to retain, in id order.
+///
+/// Without any inclusion selector all components are included. Excluded components
+/// are removed from the included ones. Every selector must match a component.
+pub(crate) fn select_components(
+    spec: &str,
+    origin: &str,
+    locked_app: &LockedApp,
+) -> Result<Vec<String>> {
+    let component_triggers = |component_id: &str| {
+        locked_app
+            .triggers
+            .iter()
+            .filter(|trigger| {
+                trigger
+                    .trigger_config
+                    .get("component")
+                    .and_then(|c| c.as_str())
+                    == Some(component_id)
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut included = BTreeSet::new();
+    let mut excluded = BTreeSet::new();
+    let mut has_inclusions = false;
+    for entry in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (selector, matched) = match entry.strip_prefix(EXCLUSION_PREFIX) {
+            Some(selector) => (selector.trim(), &mut excluded),
+            None => {
+                has_inclusions = true;
+                (entry, &mut included)
+            }
+        };
+        let selector = Selector::parse(selector, origin)?;
+        let matches = locked_app
+            .components
+            .iter()
+            .filter(|component| selector.matches(&component.id, &component_triggers(&component.id)))
+            .map(|component| component.id.clone())
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            bail!("{origin} entry {entry:?} matches no component of the application");
+        }
+        matched.extend(matches);
+    }
+    if !has_inclusions {
+        included.extend(locked_app.components.iter().map(|c| c.id.clone()));
+    }
+    let retained = included.difference(&excluded).cloned().collect::<Vec<_>>();
+    if retained.is_empty() {
+        bail!("{origin} excludes every component of the application");
+    }
+    Ok(retained)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants;
+
+    fn locked_app() -> LockedApp {
+        let component = |id: &str| {
+            format!(
+                r#"{{ "id": "{id}", "source": {{ "content_type": "application/wasm", "source": "file:///{id}.wasm" }} }}"#
+            )
+        };
+        let app_json = format!(
+            r#"
+            {{
+                "spin_lock_version": 1,
+                "components": [{}],
+                "triggers": [
+                    {{ "id": "t1", "trigger_type": "http", "trigger_config": {{ "component": "api-users", "route": "/api/users/..." }} }},
+                    {{ "id": "t2", "trigger_type": "http", "trigger_config": {{ "component": "api-orders", "route": "/api/orders" }} }},
+                    {{ "id": "t3", "trigger_type": "http", "trigger_config": {{ "component": "apiary", "route": "/apiary" }} }},
+                    {{ "id": "t4", "trigger_type": "redis", "trigger_config": {{ "component": "order-worker", "channel": "orders.created" }} }},
+                    {{ "id": "t5", "trigger_type": "mqtt", "trigger_config": {{ "component": "telemetry", "topic": "devices/telemetry" }} }},
+                    {{ "id": "t6", "trigger_type": "http", "trigger_config": {{ "component": "internal", "route": {{ "private": true }} }} }}
+                ]
+            }}"#,
+            [
+                "api-users",
+                "api-orders",
+                "apiary",
+                "order-worker",
+                "telemetry",
+                "internal"
+            ]
+            .map(component)
+            .join(",")
+        );
+        LockedApp::from_json(app_json.as_bytes()).unwrap()
+    }
+
+    fn select(spec: &str) -> Result<Vec<String>> {
+        select_components(
+            spec,
+            constants::SPIN_COMPONENTS_TO_RETAIN_ENV,
+            &locked_app(),
+        )
+    }
+
+    #[test]
+    fn selects_by_id_and_glob() {
+        assert_eq!(select("apiary").unwrap(), ["apiary"]);
+        assert_eq!(select("api-*").unwrap(), ["api-orders", "api-users"]);
+        assert_eq!(
+            select("api-?sers, telemetry").unwrap(),
+            ["api-users", "telemetry"]
+        );
+    }
+
+    #[test]
+    fn selects_by_route_and_channel() {
+        assert_eq!(select("route:/api").unwrap(), ["api-orders", "api-users"]);
+        assert_eq!(select("route:/api/users/").unwrap(), ["api-users"]);
+        assert_eq!(select("channel:orders.*").unwrap(), ["order-worker"]);
+        assert_eq!(
+            select("channel:devices/*,route:/apiary").unwrap(),
+            ["apiary", "telemetry"]
+        );
+    }
+
+    #[test]
+    fn excludes_components() {
+        assert_eq!(
+            select("!internal,!channel:*").unwrap(),
+            ["api-orders", "api-users", "apiary"]
+        );
+        assert_eq!(select("route:/api,!api-orders").unwrap(), ["api-users"]);
+        assert_eq!(select("").unwrap().len(), 6);
+    }
+
+    #[test]
+    fn rejects_selectors_matching_nothing() {
+        let err = select("api-users,billing").unwrap_err().to_string();
+        assert!(err.contains("\"billing\""), "{err}");
+        assert!(
+            err.starts_with(constants::SPIN_COMPONENTS_TO_RETAIN_ENV),
+            "{err}"
+        );
+        assert!(select("!route:/admin").is_err());
+        assert!(select("*,!*").is_err());
+        assert!(select("api-[").is_err());
+    }
+
+    #[test]
+    fn names_the_origin_of_selectors_in_errors() {
+        let origin = "image annotation dev.spinkube.spin.components-to-retain";
+        for spec in ["billing", "*,!*", "api-["] {
+            let err = select_components(spec, origin, &locked_app()).unwrap_err();
+            assert!(format!("{err:#}").contains(origin), "{err:#}");
+            assert!(
+                !format!("{err:#}").contains(constants::SPIN_COMPONENTS_TO_RETAIN_ENV),
+                "{err:#}"
+            );
+        }
+    }
+}
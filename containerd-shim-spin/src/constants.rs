@@ -3,21 +3,241 @@
 pub(crate) const SPIN_ADDR_DEFAULT: &str = "0.0.0.0:80";
 /// SPIN_HTTP_LISTEN_ADDR_ENV is the environment variable that can be used to
 /// override the default address and port that the Spin HTTP trigger listens on.
+/// It is a comma separated list of listen addresses, each either `<host>:<port>`
+/// (binding every address the host resolves to) or `unix:<path>` for a Unix
+/// domain socket, e.g. `0.0.0.0:80,[::]:80,0.0.0.0:8080,unix:/run/spin/http.sock`.
+/// The HTTP trigger binds the first TCP address and the shim forwards connections
+/// from all other addresses to it, or from all Unix domain sockets to a reserved
+/// loopback port. Components see the shim as the client of forwarded connections,
+/// as the client address cannot be passed on. For dual-stack listeners that keep
+/// client addresses, list `[::]:<port>` alone, which the trigger binds for both IPv4
+/// and IPv6 unless the node sets `net.ipv6.bindv6only`.
 pub(crate) const SPIN_HTTP_LISTEN_ADDR_ENV: &str = "SPIN_HTTP_LISTEN_ADDR";
+/// SPIN_SHIM_LOG_FORMAT_ENV is the environment variable of the shim process that
+/// selects the format of its logs: `text` (the default) or `json`, which writes one
+/// JSON object per line carrying the shim id, the app id (`HOSTNAME`) and,
+/// where relevant, the trigger type and component id.
+pub(crate) const SPIN_SHIM_LOG_FORMAT_ENV: &str = "SPIN_SHIM_LOG_FORMAT";
+/// SPIN_SHIM_LOG_LEVEL_ENV is the environment variable of the shim process that
+/// sets its log level (`error` by default).
+pub(crate) const SPIN_SHIM_LOG_LEVEL_ENV: &str = "SPIN_SHIM_LOG_LEVEL";
+/// SPIN_HEALTH_LISTEN_ADDR_ENV is the environment variable that enables the shim's
+/// own health endpoints on the given address and port. `/healthz` reports liveness
+/// once the application is loaded and `/readyz` reports readiness, including the
+/// status of each trigger, once all triggers have started and, for the HTTP trigger,
+/// accept connections. Triggers restarting or whose exit is ignored do not block it.
+pub(crate) const SPIN_HEALTH_LISTEN_ADDR_ENV: &str = "SPIN_HEALTH_LISTEN_ADDR";
+/// Interval at which the shim checks whether a trigger accepts connections.
+pub(crate) const TRIGGER_LISTENING_POLL_INTERVAL_MS: u64 = 50;
+/// SPIN_METRICS_LISTEN_ADDR_ENV is the environment variable that enables the shim's
+/// Prometheus metrics endpoint (`/metrics`) on the given address and port.
+pub(crate) const SPIN_METRICS_LISTEN_ADDR_ENV: &str = "SPIN_METRICS_LISTEN_ADDR";
+/// SPIN_TLS_CERT_ENV is the environment variable that can be used to point the
+/// Spin HTTP trigger at a PEM encoded certificate chain to serve HTTPS with.
+/// Must be set together with [`SPIN_TLS_KEY_ENV`].
+pub(crate) const SPIN_TLS_CERT_ENV: &str = "SPIN_TLS_CERT";
+/// SPIN_TLS_KEY_ENV is the environment variable that can be used to point the
+/// Spin HTTP trigger at the PEM encoded private key for [`SPIN_TLS_CERT_ENV`].
+pub(crate) const SPIN_TLS_KEY_ENV: &str = "SPIN_TLS_KEY";
+/// SPIN_TLS_CERT_PATH_DEFAULT is the location the certificate chain is picked up
+/// from when [`SPIN_TLS_CERT_ENV`] is not set. Matches the key name of a
+/// Kubernetes TLS secret mounted at `/tls`.
+pub(crate) const SPIN_TLS_CERT_PATH_DEFAULT: &str = "/tls/tls.crt";
+/// SPIN_TLS_KEY_PATH_DEFAULT is the location the private key is picked up from
+/// when [`SPIN_TLS_KEY_ENV`] is not set.
+pub(crate) const SPIN_TLS_KEY_PATH_DEFAULT: &str = "/tls/tls.key";
 /// RUNTIME_CONFIG_PATH specifies the expected location and name of the runtime
 /// config for a Spin application. The runtime config should be loaded into the
 /// root `/` of the container.
 pub(crate) const RUNTIME_CONFIG_PATH: &str = "/runtime-config.toml";
+/// RUNTIME_CONFIG_DIR is the directory of runtime config fragments merged, in the
+/// lexical order of their file names, on top of [`RUNTIME_CONFIG_PATH`].
+pub(crate) const RUNTIME_CONFIG_DIR: &str = "/runtime-config.d";
+/// Location of the runtime config resolved from [`RUNTIME_CONFIG_PATH`] and the
+/// fragments in [`RUNTIME_CONFIG_DIR`], which is the one handed to Spin. It only
+/// exists while Spin reads it, as it may hold secrets.
+pub(crate) const RUNTIME_CONFIG_RESOLVED_PATH: &str = "/.runtime-config.resolved.toml";
+/// Root of the Spin cache the application components and files are loaded from
+pub(crate) const SPIN_CACHE_DIR: &str = "/.cache";
 /// Describes an OCI layer with Wasm content
 pub(crate) const OCI_LAYER_MEDIA_TYPE_WASM: &str = "application/vnd.wasm.content.layer.v1+wasm";
+/// Media type of the detached signature layer of a Spin application: the base64
+/// encoded ECDSA P-256 signature of the Spin application config layer, as produced
+/// by `cosign sign-blob`
+pub(crate) const OCI_LAYER_MEDIA_TYPE_SIGNATURE: &str =
+    "application/vnd.spinkube.spin.signature.v1+base64";
 /// Expected location of the Spin manifest when loading from a file rather than
 /// an OCI image
 pub(crate) const SPIN_MANIFEST_FILE_PATH: &str = "/spin.toml";
 /// Known prefix for the Spin application variables environment variable
 /// provider: https://github.com/fermyon/spin/blob/436ad589237c02f7aa4693e984132808fd80b863/crates/variables/src/provider/env.rs#L9
 pub(crate) const SPIN_APPLICATION_VARIABLE_PREFIX: &str = "SPIN_VARIABLE";
+/// SPIN_OCI_IMAGE_REFERENCE_ENV is the environment variable that carries the reference
+/// of the OCI image a Spin application is distributed with, such as
+/// `ghcr.io/spinkube/spin-rust-hello:v1`. It is recorded in the locked application,
+/// startup logs and telemetry resource attributes. Runwasi only hands engines the
+/// arguments, environment and layers of a container, not its runtime spec, so the
+/// image name CRI annotates containers with (`io.kubernetes.cri.image-name`) and
+/// the manifest digest are not available to the shim. The manifest digest is only
+/// recorded if the reference is pinned to it (`...@sha256:<digest>`).
+pub(crate) const SPIN_OCI_IMAGE_REFERENCE_ENV: &str = "SPIN_OCI_IMAGE_REFERENCE";
+/// Image reference recorded as the origin of the locked application when
+/// [`SPIN_OCI_IMAGE_REFERENCE_ENV`] is not set. Logs and telemetry omit it.
+pub(crate) const SPIN_OCI_IMAGE_REFERENCE_UNKNOWN: &str = "unknown";
+/// Standard OpenTelemetry environment variable holding resource attributes
+pub(crate) const OTEL_RESOURCE_ATTRIBUTES_ENV: &str = "OTEL_RESOURCE_ATTRIBUTES";
+/// Prefix of the standard OpenTelemetry environment variables
+pub(crate) const OTEL_ENV_PREFIX: &str = "OTEL_";
+/// Prefixes of the container environment variables exported to the process
+/// environment, as they are read from it only: the OpenTelemetry configuration,
+/// the AWS credentials and region of the SQS trigger, and the pooling allocator
+/// settings of Spin.
+pub(crate) const PROCESS_ENV_PREFIXES: &[&str] = &[OTEL_ENV_PREFIX, "AWS_", "SPIN_WASMTIME_"];
+/// Container environment variables exported to the process environment besides
+/// those matching [`PROCESS_ENV_PREFIXES`], such as the log filter of Spin.
+pub(crate) const PROCESS_ENV_VARS: &[&str] = &["RUST_LOG"];
+/// SPIN_VARIABLES_DIR_ENV is the environment variable that enables reading application
+/// variables from a directory holding one file per variable, such as a mounted Secret
+/// or ConfigMap. Changes to the files are picked up without restarting the pod.
+pub(crate) const SPIN_VARIABLES_DIR_ENV: &str = "SPIN_VARIABLES_DIR";
 /// Working directory for Spin applications
 pub(crate) const SPIN_TRIGGER_WORKING_DIR: &str = "/";
 /// Defines the subset of application components that should be executable by the shim
 /// If empty or DNE, all components will be supported
+///
+/// It is a comma separated list of selectors: component ids or glob patterns over
+/// them (e.g. `api-*`), `route:<path prefix>` for the components of HTTP triggers
+/// under a route prefix and `channel:<pattern>` for the components of Redis triggers
+/// by channel or MQTT triggers by topic. Selectors prefixed with `!` exclude the
+/// components they match; with exclusions only, all other components are retained.
+///
+/// Set on the shim process rather than a container, it skips precompiling the
+/// components it does not select in the images of the node, like
+/// [`SPIN_COMPONENTS_TO_RETAIN_ANNOTATION`], without retaining them any less.
 pub(crate) const SPIN_COMPONENTS_TO_RETAIN_ENV: &str = "SPIN_COMPONENTS_TO_RETAIN";
+/// Annotation of the Spin application config layer of an image selecting the
+/// components whose layers are precompiled, with the selectors of
+/// [`SPIN_COMPONENTS_TO_RETAIN_ENV`].
+///
+/// Wasm layers are precompiled once per image, before the container environment is
+/// available, so the retention configured for a container cannot be known then. The
+/// annotation does not change which components a container retains: retained
+/// components whose layers were not precompiled are compiled when the container
+/// starts. No tooling sets it yet; it is experimental and may change.
+pub(crate) const SPIN_COMPONENTS_TO_RETAIN_ANNOTATION: &str =
+    "dev.spinkube.spin.components-to-retain";
+/// SPIN_TRIGGERS_TO_RUN_ENV is the environment variable that restricts the trigger
+/// types the shim runs to a comma separated list, e.g. `redis,mqtt`, so that the
+/// triggers of one application can be split across deployments. Every listed trigger
+/// type must be used by the application. If unset, all triggers run.
+pub(crate) const SPIN_TRIGGERS_TO_RUN_ENV: &str = "SPIN_TRIGGERS_TO_RUN";
+/// SPIN_SHUTDOWN_DRAIN_PERIOD_ENV is the environment variable that can be used to
+/// configure how many seconds the shim waits for in-flight component invocations to
+/// complete after receiving a termination signal. HTTP triggers stop accepting
+/// connections and no new invocations are started during this period.
+/// If unset or `0`, in-flight invocations are aborted immediately.
+pub(crate) const SPIN_SHUTDOWN_DRAIN_PERIOD_ENV: &str = "SPIN_SHUTDOWN_DRAIN_PERIOD";
+/// SPIN_SIGNATURE_POLICY_ENV is the environment variable of the shim process that
+/// selects whether the signature of OCI applications is verified before they run:
+/// `off` (the default), `warn` (log unsigned or invalid applications and run them
+/// anyway) or `enforce` (refuse to run them). Applications loaded from
+/// [`SPIN_MANIFEST_FILE_PATH`] are unsigned. Containers may set it to a stricter
+/// policy than the one of the node, but never to a more lenient one.
+pub(crate) const SPIN_SIGNATURE_POLICY_ENV: &str = "SPIN_SIGNATURE_POLICY";
+/// SPIN_SIGNATURE_PUBLIC_KEY_ENV is the environment variable of the shim process
+/// holding the path of the PEM encoded public key signatures are verified against,
+/// e.g. a `cosign.pub` on the node. It is ignored in the environment of containers.
+pub(crate) const SPIN_SIGNATURE_PUBLIC_KEY_ENV: &str = "SPIN_SIGNATURE_PUBLIC_KEY";
+/// Default path, on the node, of the public key signatures are verified against
+pub(crate) const SPIN_SIGNATURE_PUBLIC_KEY_DEFAULT: &str = "/etc/spin/cosign.pub";
+/// SPIN_FILES_MOUNT_STRATEGY_ENV is the environment variable that selects how the
+/// static files of applications loaded from [`SPIN_MANIFEST_FILE_PATH`] are made
+/// available to components. `direct` (the default) mounts the directories in place,
+/// while `copy` copies the files into the writable directory configured by
+/// [`SPIN_FILES_COPY_DIR_ENV`], which suits files living on read-only image layers.
+pub(crate) const SPIN_FILES_MOUNT_STRATEGY_ENV: &str = "SPIN_FILES_MOUNT_STRATEGY";
+/// Directory files are copied into when the `copy` mount strategy is used
+pub(crate) const SPIN_FILES_COPY_DIR_ENV: &str = "SPIN_FILES_COPY_DIR";
+/// Default directory files are copied into when the `copy` mount strategy is used
+pub(crate) const SPIN_FILES_COPY_DIR_DEFAULT: &str = "/.spin/files";
+/// SPIN_PRECOMPILE_CACHE_DIR_ENV is the environment variable of the shim process
+/// that enables a node-wide cache of precompiled components at the given directory.
+/// The directory must only be writable by the shim.
+pub(crate) const SPIN_PRECOMPILE_CACHE_DIR_ENV: &str = "SPIN_PRECOMPILE_CACHE_DIR";
+/// SPIN_PRECOMPILE_CACHE_MAX_SIZE_ENV is the environment variable of the shim process
+/// that bounds the size in bytes of the precompiled component cache.
+pub(crate) const SPIN_PRECOMPILE_CACHE_MAX_SIZE_ENV: &str = "SPIN_PRECOMPILE_CACHE_MAX_SIZE";
+/// Default size bound of the precompiled component cache (2 GiB)
+pub(crate) const SPIN_PRECOMPILE_CACHE_MAX_SIZE_DEFAULT: u64 = 2 * 1024 * 1024 * 1024;
+/// SPIN_PRECOMPILE_DIR_ENV is the environment variable that enables precompilation
+/// of applications loaded from [`SPIN_MANIFEST_FILE_PATH`], keeping the compiled
+/// components in the given directory, typically an emptyDir volume, across container
+/// restarts. Only components the shim of the pod compiled itself are loaded from it.
+/// Its size is bounded by [`SPIN_PRECOMPILE_CACHE_MAX_SIZE_ENV`].
+pub(crate) const SPIN_PRECOMPILE_DIR_ENV: &str = "SPIN_PRECOMPILE_DIR";
+/// Directory of the container the components precompiled for an application loaded
+/// from [`SPIN_MANIFEST_FILE_PATH`], or compiled when an image did not precompile
+/// them, are loaded from. The shim creates it, refusing to use one that already
+/// exists.
+pub(crate) const SPIN_PRECOMPILED_COMPONENTS_DIR: &str = "/.spin-precompiled";
+/// SPIN_MAX_INSTANCE_MEMORY_ENV is the environment variable that limits the linear
+/// memory, in bytes, of every component instance. The limit of a single component can
+/// be overridden by suffixing the variable with the uppercased component id, replacing
+/// `-` with `_` (e.g. `SPIN_MAX_INSTANCE_MEMORY_MY_COMPONENT`). Memory growth beyond
+/// the limit fails inside the guest, which typically traps the invocation.
+pub(crate) const SPIN_MAX_INSTANCE_MEMORY_ENV: &str = "SPIN_MAX_INSTANCE_MEMORY";
+/// SPIN_MAX_INVOCATION_SECONDS_ENV is the environment variable that limits the time,
+/// in seconds, a component invocation of the cron trigger may run for. It can be
+/// overridden per component like [`SPIN_MAX_INSTANCE_MEMORY_ENV`]. Runs exceeding the
+/// limit are interrupted and logged as such. Spin does not let the shim limit the
+/// invocations of other triggers (see [`crate::limits`]).
+pub(crate) const SPIN_MAX_INVOCATION_SECONDS_ENV: &str = "SPIN_MAX_INVOCATION_SECONDS";
+/// SPIN_POOLING_ALLOCATOR_ENV is the environment variable that selects the Wasmtime
+/// instance allocator of the triggers. `true` configures the pooling allocator with the
+/// limits below, falling back to on-demand allocation if the node cannot reserve the
+/// virtual memory of the pool. `false` forces on-demand allocation. If unset, Spin's
+/// default is used.
+pub(crate) const SPIN_POOLING_ALLOCATOR_ENV: &str = "SPIN_POOLING_ALLOCATOR";
+/// Number of component instances the pool holds concurrently
+pub(crate) const SPIN_POOLING_INSTANCE_COUNT_ENV: &str = "SPIN_POOLING_INSTANCE_COUNT";
+/// Number of core instances the pool holds concurrently. Defaults to 8 per component instance.
+pub(crate) const SPIN_POOLING_CORE_INSTANCE_COUNT_ENV: &str = "SPIN_POOLING_CORE_INSTANCE_COUNT";
+/// Number of linear memories the pool holds concurrently. Defaults to 2 per component instance.
+pub(crate) const SPIN_POOLING_MEMORY_COUNT_ENV: &str = "SPIN_POOLING_MEMORY_COUNT";
+/// Number of tables the pool holds concurrently. Defaults to 4 per component instance.
+pub(crate) const SPIN_POOLING_TABLE_COUNT_ENV: &str = "SPIN_POOLING_TABLE_COUNT";
+/// Maximum size in bytes of each linear memory in the pool
+pub(crate) const SPIN_POOLING_MAX_MEMORY_SIZE_ENV: &str = "SPIN_POOLING_MAX_MEMORY_SIZE";
+/// Maximum number of elements of each table in the pool
+pub(crate) const SPIN_POOLING_TABLE_ELEMENTS_ENV: &str = "SPIN_POOLING_TABLE_ELEMENTS";
+/// SPIN_MEMORY_RESERVATION_ENV is the environment variable of the shim process that
+/// sets the virtual memory, in bytes, reserved for each linear memory (Wasmtime's
+/// static memory maximum size). Lowering it shrinks the virtual memory the pooling
+/// allocator needs. As compiled components depend on it, it applies to the shim as
+/// a whole, both when precompiling components and when running them.
+pub(crate) const SPIN_MEMORY_RESERVATION_ENV: &str = "SPIN_MEMORY_RESERVATION";
+/// SPIN_TRIGGER_RESTART_POLICY_ENV is the environment variable that configures what
+/// happens when a trigger of a multi-trigger application exits. It is either a single
+/// policy applied to all triggers or a comma separated list of `<trigger type>=<policy>`
+/// entries, e.g. `http=exit-all,redis=restart`. Policies are `exit-all` (the default,
+/// stops the whole application), `restart` (restarts the trigger with exponential
+/// backoff, reset once it keeps running for [`TRIGGER_RESTART_RESET_SECS`]) and
+/// `ignore` (keeps the other triggers running). Entries may name any trigger type of
+/// the application, those not selected by [`SPIN_TRIGGERS_TO_RUN_ENV`] being ignored.
+pub(crate) const SPIN_TRIGGER_RESTART_POLICY_ENV: &str = "SPIN_TRIGGER_RESTART_POLICY";
+/// Delay before the first restart of a trigger, doubled on every further restart
+pub(crate) const TRIGGER_RESTART_INITIAL_BACKOFF_SECS: u64 = 1;
+/// Upper bound of the delay before restarting a trigger
+pub(crate) const TRIGGER_RESTART_MAX_BACKOFF_SECS: u64 = 60;
+/// Time a trigger must keep running after being restarted for its restart count,
+/// and with it the backoff before its next restart, to be reset
+pub(crate) const TRIGGER_RESTART_RESET_SECS: u64 = 300;
+/// Exit code of the container when a trigger fails for any reason other than the
+/// ones below. Guests exiting through WASI, e.g. command trigger components calling
+/// `exit`, set the exit code of the container themselves.
+pub(crate) const EXIT_CODE_FAILURE: i32 = 1;
+/// Exit code of the container when the application could not be loaded or its
+/// triggers could not be started, like a shell failing to execute a command
+pub(crate) const EXIT_CODE_LOAD_FAILURE: i32 = 126;
+/// Exit code of the container when a component traps, like a native process aborting
+pub(crate) const EXIT_CODE_TRAP: i32 = 134;
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use spin_expressions::{Key, Provider};
+
+use crate::constants;
+
+/// Environment variables of a container, which configure both the shim and the
+/// Spin application per the [SKIP 003] proposal.
+///
+/// A shim process can run several containers, so the environment of each one is
+/// kept out of the process environment and handed to whatever needs it, except
+/// for the variables read from the process environment only, which are listed in
+/// [`constants::PROCESS_ENV_PREFIXES`] and [`constants::PROCESS_ENV_VARS`]. It also
+/// serves as the application variable provider of the container.
+///
+/// [SKIP 003]: https://github.com/spinkube/skips/tree/main/proposals/003-shim-runtime-options
+#[derive(Clone, Default)]
+pub(crate) struct ContainerEnv {
+    vars: Arc<HashMap<String, String>>,
+}
+
+impl ContainerEnv {
+    /// Creates the environment from `KEY=value` entries, as found in the OCI spec.
+    pub(crate) fn new(envs: &[String]) -> Self {
+        envs.iter()
+            .map(|v| v.split_once('=').unwrap_or((v.as_str(), "")))
+            .collect()
+    }
+
+    pub(crate) fn var(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(String::as_str)
+    }
+
+    pub(crate) fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Value of the application variable `name`, taken from the container
+    /// environment variable `<NAME>` or else from `SPIN_VARIABLE_<NAME>`.
+    pub(crate) fn application_variable(&self, name: &str) -> Option<&str> {
+        let upper = name.to_ascii_uppercase();
+        self.var(&upper).or_else(|| {
+            self.var(&format!(
+                "{}_{upper}",
+                constants::SPIN_APPLICATION_VARIABLE_PREFIX
+            ))
+        })
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for ContainerEnv {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            vars: Arc::new(
+                iter.into_iter()
+                    .map(|(k, v)| (k.into(), v.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// Values are left out as they may hold secrets
+impl fmt::Debug for ContainerEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut keys = self.vars.keys().collect::<Vec<_>>();
+        keys.sort();
+        f.debug_struct("ContainerEnv").field("keys", &keys).finish()
+    }
+}
+
+#[async_trait]
+impl Provider for ContainerEnv {
+    async fn get(&self, key: &Key) -> Result<Option<String>> {
+        Ok(self.application_variable(key.as_str()).map(str::to_string))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_oci_env_entries() {
+        let env = ContainerEnv::new(&[
+            "HOSTNAME=app-7d9f".to_string(),
+            "EMPTY=".to_string(),
+            "NO_VALUE".to_string(),
+            "URL=https://example.com/?a=b".to_string(),
+        ]);
+        assert_eq!(env.var("HOSTNAME"), Some("app-7d9f"));
+        assert_eq!(env.var("EMPTY"), Some(""));
+        assert_eq!(env.var("NO_VALUE"), Some(""));
+        assert_eq!(env.var("URL"), Some("https://example.com/?a=b"));
+        assert_eq!(env.var("MISSING"), None);
+    }
+
+    #[tokio::test]
+    async fn resolves_application_variables() {
+        let env = ContainerEnv::from_iter([
+            ("SPIN_VARIABLE_API_KEY", "ignored"),
+            ("API_KEY", "unprefixed"),
+            ("SPIN_VARIABLE_REGION", "eu-west-1"),
+            ("lower_case", "ignored"),
+        ]);
+        let get = |name: &str| {
+            let env = env.clone();
+            let key = Key::new(name).unwrap();
+            async move { env.get(&key).await.unwrap() }
+        };
+        assert_eq!(get("api_key").await.as_deref(), Some("unprefixed"));
+        assert_eq!(get("region").await.as_deref(), Some("eu-west-1"));
+        assert_eq!(get("lower_case").await, None);
+    }
+
+    #[tokio::test]
+    async fn apps_in_the_same_process_see_only_their_own_variables() {
+        let first = ContainerEnv::from_iter([("GREETING", "hello")]);
+        let second = ContainerEnv::from_iter([("SPIN_VARIABLE_TOKEN", "s3cret")]);
+        let greeting = Key::new("greeting").unwrap();
+        let token = Key::new("token").unwrap();
+
+        assert_eq!(
+            first.get(&greeting).await.unwrap().as_deref(),
+            Some("hello")
+        );
+        assert_eq!(first.get(&token).await.unwrap(), None);
+        assert_eq!(second.get(&greeting).await.unwrap(), None);
+        assert_eq!(second.get(&token).await.unwrap().as_deref(), Some("s3cret"));
+        // Neither environment reached the process environment
+        assert!(std::env::var("GREETING").is_err());
+        assert!(std::env::var("SPIN_VARIABLE_TOKEN").is_err());
+    }
+
+    #[test]
+    fn debug_omits_values() {
+        let env = ContainerEnv::from_iter([("SPIN_VARIABLE_TOKEN", "s3cret")]);
+        let debug = format!("{env:?}");
+        assert!(debug.contains("SPIN_VARIABLE_TOKEN"));
+        assert!(!debug.contains("s3cret"));
+    }
+}
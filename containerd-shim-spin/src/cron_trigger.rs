@@ -0,0 +1,350 @@
+use std::{future::Future, pin::pin, str::FromStr, sync::Arc, time::Instant};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use futures::{
+    future::{self, Either},
+    stream::FuturesUnordered,
+    StreamExt,
+};
+use log::{error, info, warn};
+use serde::Deserialize;
+use spin_app::App;
+use spin_factors::RuntimeFactors;
+use spin_trigger::{cli::NoCliArgs, Trigger, TriggerApp};
+
+use crate::{limits::InstanceLimits, metrics::Metrics, trigger};
+
+/// Interface exported by the components run by the cron trigger
+const WASI_CLI_RUN_INTERFACE: &str = "wasi:cli/run@0.2.0";
+
+/// Runs components on a schedule from within the long-lived pod.
+///
+/// Each `[[trigger.cron]]` entry of the manifest names a component and a cron
+/// expression with a seconds field, e.g. `0 */5 * * * *`, evaluated in the
+/// optional IANA `timezone` of the entry (UTC by default). At every tick the
+/// component's `wasi:cli/run` export is called, like the command trigger does.
+/// A failing run is logged and does not stop the schedule. Ticks that occur
+/// while a previous run is still in progress follow the `overlap` policy.
+pub(crate) struct CronTrigger {
+    jobs: Vec<CronJob>,
+    limits: Arc<InstanceLimits>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+#[derive(Deserialize)]
+struct CronTriggerConfig {
+    component: String,
+    cron_expression: String,
+    #[serde(default)]
+    timezone: Option<String>,
+    #[serde(default)]
+    overlap: OverlapPolicy,
+}
+
+/// What happens when a tick occurs while the previous run is still in progress
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OverlapPolicy {
+    /// The tick is skipped
+    #[default]
+    Skip,
+    /// Another run starts concurrently
+    Allow,
+}
+
+/// Source of the current time of the scheduler. Sleeping is left to the tokio
+/// timer, so tests can drive the scheduler with paused tokio time.
+trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+struct CronJob {
+    component: String,
+    schedule: Schedule,
+    timezone: Tz,
+    overlap: OverlapPolicy,
+}
+
+impl CronJob {
+    fn new(trigger_id: &str, config: CronTriggerConfig) -> Result<Self> {
+        let schedule = Schedule::from_str(&config.cron_expression).with_context(|| {
+            format!(
+                "invalid cron expression {:?} for trigger {trigger_id}",
+                config.cron_expression
+            )
+        })?;
+        let timezone = match &config.timezone {
+            Some(timezone) => Tz::from_str(timezone).map_err(|err| {
+                anyhow!("invalid timezone {timezone:?} for trigger {trigger_id}: {err}")
+            })?,
+            None => Tz::UTC,
+        };
+        Ok(Self {
+            component: config.component,
+            schedule,
+            timezone,
+            overlap: config.overlap,
+        })
+    }
+
+    fn next_tick(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule
+            .after(&now.with_timezone(&self.timezone))
+            .next()
+            .map(|tick| tick.with_timezone(&Utc))
+    }
+
+    /// Calls `invoke` at every tick of the schedule until it has no upcoming ticks,
+    /// counting failed runs in `metrics`.
+    async fn run<C, I, Fut>(&self, clock: &C, metrics: Option<&Metrics>, invoke: I)
+    where
+        C: Clock,
+        I: Fn() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        info!(
+            trigger = "cron", component = self.component.as_str();
+            " >>> scheduling component {} with {} ({}, overlap {:?})",
+            self.component, self.schedule, self.timezone, self.overlap
+        );
+        let mut in_flight = FuturesUnordered::new();
+        while let Some(tick) = self.next_tick(clock.now()) {
+            let delay = (tick - clock.now()).to_std().unwrap_or_default();
+            let mut sleep = pin!(tokio::time::sleep(delay));
+            // Keep driving the runs in progress while waiting for the tick
+            while !in_flight.is_empty() {
+                match future::select(sleep.as_mut(), in_flight.next()).await {
+                    Either::Left(_) => break,
+                    Either::Right((Some(result), _)) => self.log_result(metrics, result),
+                    Either::Right((None, _)) => {}
+                }
+            }
+            sleep.await;
+            if !in_flight.is_empty() && self.overlap == OverlapPolicy::Skip {
+                warn!(
+                    trigger = "cron", component = self.component.as_str();
+                    "skipping scheduled run of component {} at {tick}: previous run still in progress",
+                    self.component
+                );
+                continue;
+            }
+            in_flight.push(invoke());
+        }
+        while let Some(result) = in_flight.next().await {
+            self.log_result(metrics, result);
+        }
+        info!(
+            trigger = "cron", component = self.component.as_str();
+            " >>> schedule of component {} has no upcoming runs",
+            self.component
+        );
+    }
+
+    fn log_result(&self, metrics: Option<&Metrics>, result: Result<()>) {
+        if let Err(err) = result {
+            if let Some(metrics) = metrics {
+                metrics.record_invocation_error(&self.component);
+            }
+            error!(
+                trigger = "cron", component = self.component.as_str();
+                "scheduled run of component {} failed: {err:?}",
+                self.component
+            );
+        }
+    }
+}
+
+async fn invoke<F: RuntimeFactors>(
+    trigger_app: &TriggerApp<CronTrigger, F>,
+    component: &str,
+    limits: &InstanceLimits,
+    metrics: Option<&Metrics>,
+) -> Result<()> {
+    let instance_builder = trigger_app.prepare(component)?;
+    let started = Instant::now();
+    let (instance, mut store) = instance_builder.instantiate(()).await?;
+    if let Some(metrics) = metrics {
+        metrics.record_instantiation_duration(component, started.elapsed());
+    }
+    let max_invocation_time = limits.max_invocation_time(component);
+    if let Some(max) = max_invocation_time {
+        store.set_deadline(started + max);
+    }
+    let run = {
+        let mut exports = instance.exports(&mut store);
+        let mut interface = exports.instance(WASI_CLI_RUN_INTERFACE).with_context(|| {
+            format!("component {component} does not export {WASI_CLI_RUN_INTERFACE}")
+        })?;
+        interface.typed_func::<(), (Result<(), ()>,)>("run")?
+    };
+    let (result,) = run.call_async(&mut store, ()).await.map_err(|err| {
+        match (max_invocation_time, err.downcast_ref::<wasmtime::Trap>()) {
+            (Some(max), Some(wasmtime::Trap::Interrupt)) => err.context(format!(
+                "component {component} exceeded its execution time limit of {}s",
+                max.as_secs()
+            )),
+            _ => err,
+        }
+    })?;
+    result.map_err(|()| anyhow!("component {component} returned an error"))
+}
+
+impl<F: RuntimeFactors> Trigger<F> for CronTrigger {
+    const TYPE: &'static str = "cron";
+    type CliArgs = NoCliArgs;
+    type InstanceState = ();
+
+    fn new(_cli_args: Self::CliArgs, app: &App) -> Result<Self> {
+        let jobs = app
+            .trigger_configs::<CronTriggerConfig>(<Self as Trigger<F>>::TYPE)?
+            .into_iter()
+            .map(|(id, config)| CronJob::new(id, config))
+            .collect::<Result<_>>()?;
+        let state = trigger::app_state()?;
+        Ok(Self {
+            jobs,
+            limits: state.limits,
+            metrics: state.metrics,
+        })
+    }
+
+    async fn run(self, trigger_app: TriggerApp<Self, F>) -> Result<()> {
+        let trigger_app = &trigger_app;
+        let limits = &*self.limits;
+        let metrics = self.metrics.as_deref();
+        future::join_all(self.jobs.iter().map(|job| {
+            job.run(&SystemClock, metrics, move || {
+                invoke(trigger_app, &job.component, limits, metrics)
+            })
+        }))
+        .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// Clock following paused tokio time from a fixed start.
+    struct MockClock {
+        start: DateTime<Utc>,
+        started: tokio::time::Instant,
+    }
+
+    impl MockClock {
+        fn starting_at(start: &str) -> Self {
+            Self {
+                start: start.parse().unwrap(),
+                started: tokio::time::Instant::now(),
+            }
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.start + chrono::Duration::from_std(self.started.elapsed()).unwrap()
+        }
+    }
+
+    fn job(cron_expression: &str, timezone: Option<&str>, overlap: OverlapPolicy) -> CronJob {
+        CronJob::new(
+            "t1",
+            CronTriggerConfig {
+                component: "job".to_string(),
+                cron_expression: cron_expression.to_string(),
+                timezone: timezone.map(str::to_string),
+                overlap,
+            },
+        )
+        .unwrap()
+    }
+
+    /// Runs the job to the end of its schedule, returning the start time of each
+    /// run. Every run takes `duration`.
+    async fn run_job(job: &CronJob, clock: &MockClock, duration: Duration) -> Vec<DateTime<Utc>> {
+        let runs = Arc::new(Mutex::new(Vec::new()));
+        job.run(clock, None, || {
+            runs.lock().unwrap().push(clock.now());
+            async move {
+                tokio::time::sleep(duration).await;
+                Ok(())
+            }
+        })
+        .await;
+        let runs = runs.lock().unwrap().clone();
+        runs
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = |cron_expression: &str, timezone: Option<&str>| CronTriggerConfig {
+            component: "job".to_string(),
+            cron_expression: cron_expression.to_string(),
+            timezone: timezone.map(str::to_string),
+            overlap: OverlapPolicy::default(),
+        };
+        let err = CronJob::new("t1", config("every five minutes", None))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("trigger t1"));
+        assert!(CronJob::new("t1", config("0 */5 * * * *", Some("Mars/Olympus"))).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evaluates_schedule_in_timezone() {
+        // 09:00 in Amsterdam is 08:00 UTC in winter and 07:00 UTC in summer
+        let job = job(
+            "0 0 9 1 1,7 * 2030",
+            Some("Europe/Amsterdam"),
+            OverlapPolicy::Skip,
+        );
+        let clock = MockClock::starting_at("2029-12-31T12:00:00Z");
+        let runs = run_job(&job, &clock, Duration::from_secs(1)).await;
+        assert_eq!(
+            runs,
+            vec![
+                Utc.with_ymd_and_hms(2030, 1, 1, 8, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2030, 7, 1, 7, 0, 0).unwrap(),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_ticks_while_running() {
+        // Hourly on January 1st, with runs taking 90 minutes
+        let job = job("0 0 * 1 1 * 2030", None, OverlapPolicy::Skip);
+        let clock = MockClock::starting_at("2029-12-31T23:30:00Z");
+        let runs = run_job(&job, &clock, Duration::from_secs(90 * 60)).await;
+        assert_eq!(runs.len(), 12);
+        assert_eq!(runs[0], Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(runs[1], Utc.with_ymd_and_hms(2030, 1, 1, 2, 0, 0).unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn allows_overlapping_runs() {
+        let job = job("0 0 * 1 1 * 2030", None, OverlapPolicy::Allow);
+        let clock = MockClock::starting_at("2029-12-31T23:30:00Z");
+        let runs = run_job(&job, &clock, Duration::from_secs(90 * 60)).await;
+        assert_eq!(runs.len(), 24);
+        assert_eq!(runs[1], Utc.with_ymd_and_hms(2030, 1, 1, 1, 0, 0).unwrap());
+    }
+}
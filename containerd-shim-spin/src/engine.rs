@@ -1,7 +1,13 @@
 use std::{
-    collections::{hash_map::DefaultHasher, HashSet},
-    env,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt,
+    fs::{self, DirBuilder, File},
     hash::{Hash, Hasher},
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result};
@@ -10,34 +16,54 @@ use containerd_shim_wasm::{
     sandbox::WasmLayer,
     version,
 };
-use futures::future;
+use futures::{
+    future::{self, AbortHandle, LocalBoxFuture},
+    stream::FuturesUnordered,
+    Future, FutureExt, StreamExt,
+};
 use log::info;
 use spin_app::locked::LockedApp;
 use spin_factor_outbound_networking::validate_service_chaining_for_components;
-use spin_trigger::cli::NoCliArgs;
-use spin_trigger_http::HttpTrigger;
-use spin_trigger_redis::RedisTrigger;
-use tokio::runtime::Runtime;
-use trigger_command::CommandTrigger;
-use trigger_mqtt::MqttTrigger;
-use trigger_sqs::SqsTrigger;
+use spin_trigger::loader::ComponentLoader;
+use tokio::{runtime::Runtime, sync::watch};
+use wasmtime_wasi::I32Exit;
 
 use crate::{
+    compile_cache::CompileCache,
+    component_selection::select_components,
     constants,
-    source::Source,
+    container_env::ContainerEnv,
+    file_precompile::{self, ArtifactKey},
+    file_variables::FileVariablesProvider,
+    health::{self, HealthState, TriggerStatus},
+    invocations::Invocations,
+    limits::InstanceLimits,
+    logging,
+    metrics::{self, Metrics},
+    pooling::{self, AllocatorMode},
+    runtime_config::{self, ResolvedRuntimeConfig},
+    server,
+    signature::SignatureVerifier,
+    source::{self, OciImage, Source},
     trigger::{
-        self, get_supported_triggers, COMMAND_TRIGGER_TYPE, HTTP_TRIGGER_TYPE, MQTT_TRIGGER_TYPE,
-        REDIS_TRIGGER_TYPE, SQS_TRIGGER_TYPE,
+        parse_restart_policies, restart_backoff, AppState, RestartPolicy, StartedTrigger,
+        TriggerArgs, TriggerRegistry,
     },
     utils::{
-        configure_application_variables_from_environment_variables, initialize_cache,
-        is_wasm_content, parse_addr,
+        configure_telemetry, export_process_env, get_drain_period, initialize_cache,
+        is_wasm_content, parse_addr, validate_required_variables,
     },
 };
 
 #[derive(Clone)]
 pub struct SpinEngine {
     pub(crate) wasmtime_engine: wasmtime::Engine,
+    /// Key of the components precompiled for applications loaded from a file
+    /// manifest, generated when the shim starts
+    artifact_key: Option<ArtifactKey>,
+    /// Signature verification configured for the node, loaded when the shim starts
+    /// as the public key is read from the host filesystem
+    signature_verifier: SignatureVerifier,
 }
 
 impl Default for SpinEngine {
@@ -50,8 +76,14 @@ impl Default for SpinEngine {
         // TODO: This can be removed once the Wasmtime fix is brought into Spin
         // Issue to track: https://github.com/fermyon/spin/issues/2889
         config.native_unwind_info(false);
+        pooling::configure_memory_reservation(&mut config);
+        let artifact_key = ArtifactKey::generate()
+            .inspect_err(|err| log::warn!("not precompiling file based applications: {err:?}"))
+            .ok();
         Self {
             wasmtime_engine: wasmtime::Engine::new(&config).unwrap(),
+            artifact_key,
+            signature_verifier: SignatureVerifier::from_node_env(),
         }
     }
 }
@@ -62,38 +94,56 @@ impl Engine for SpinEngine {
     }
 
     fn run_wasi(&self, ctx: &impl RuntimeContext) -> Result<i32> {
-        // Set the container environment variables which will be collected by Spin's
-        // [environment variable provider]. We use these variables to configure both the Spin runtime
-        // and the Spin application per the [SKIP 003] proposal.
-        //
-        // TODO: This is a temporary solution to allow Spin to collect the container environment variables.
-        // We should later look into other variable providers to collect container variables.
-        //
-        // [SKIP 003]: https://github.com/spinkube/skips/tree/main/proposals/003-shim-runtime-options
-        // [environment variable provider]: https://github.com/fermyon/spin/blob/v3.0.0/crates/variables/src/env.rs
-        ctx.envs().iter().for_each(|v| {
-            let (key, value) = v.split_once('=').unwrap_or((v.as_str(), ""));
-            env::set_var(key, value);
-        });
+        // The container environment configures both the Spin runtime and the Spin
+        // application. It is passed to whatever reads it rather than set on the
+        // process, which may be shared with other containers.
+        let env = ContainerEnv::new(ctx.envs());
+
+        if let Some(app_id) = env.var("HOSTNAME") {
+            logging::set_app_id(app_id)?;
+        }
+
+        let drain_period = get_drain_period(&env)?;
+
+        // Spin's telemetry and allocator and the SQS trigger read the process
+        // environment, which is only written to here, before the runtime starts
+        // any threads
+        export_process_env(&env);
+        let image = match ctx.entrypoint().source {
+            containerd_shim_wasm::container::Source::Oci(layers) => {
+                Some(OciImage::new(&env, layers))
+            }
+            containerd_shim_wasm::container::Source::File(_) => None,
+        };
+        configure_telemetry(&env, &telemetry_attributes(image.as_ref()));
 
         info!("setting up wasi");
         let rt = Runtime::new().context("failed to create runtime")?;
 
-        let (abortable, abort_handle) = futures::future::abortable(self.wasm_exec_async(ctx));
-        ctrlc::set_handler(move || abort_handle.abort())?;
+        let (stop, stop_signal) = watch::channel(false);
+        ctrlc::set_handler(move || {
+            stop.send_replace(true);
+        })?;
 
-        match rt.block_on(abortable) {
-            Ok(Ok(())) => {
+        let result = rt.block_on(self.wasm_exec_async(ctx, &env, stop_signal, drain_period));
+        // Invocations cut off after the drain period may still be running
+        rt.shutdown_background();
+        match result {
+            Ok(()) => {
                 info!("run_wasi shut down: exiting");
                 Ok(0)
             }
-            Ok(Err(err)) => {
-                log::error!("run_wasi ERROR >>>  failed: {:?}", err);
-                Err(err)
-            }
-            Err(aborted) => {
-                info!("Received signal to abort: {:?}", aborted);
-                Ok(0)
+            Err(err) => {
+                let code = exit_code(&err);
+                if code == 0 {
+                    info!("run_wasi shut down: guest exited successfully");
+                } else {
+                    log::error!(
+                        "run_wasi ERROR >>>  failed with exit code {code}: {:?}",
+                        err
+                    );
+                }
+                Ok(code)
             }
         }
     }
@@ -105,6 +155,7 @@ impl Engine for SpinEngine {
     fn supported_layers_types() -> &'static [&'static str] {
         &[
             constants::OCI_LAYER_MEDIA_TYPE_WASM,
+            constants::OCI_LAYER_MEDIA_TYPE_SIGNATURE,
             spin_oci::client::ARCHIVE_MEDIATYPE,
             spin_oci::client::DATA_MEDIATYPE,
             spin_oci::client::SPIN_APPLICATION_MEDIA_TYPE,
@@ -112,10 +163,37 @@ impl Engine for SpinEngine {
     }
 
     fn precompile(&self, layers: &[WasmLayer]) -> Result<Vec<Option<Vec<u8>>>> {
-        // Runwasi expects layers to be returned in the same order, so wrap each layer in an option, setting non Wasm layers to None
+        let compile_cache = self.can_precompile().and_then(|hash| {
+            // Precompilation does not depend on the cache, which is only an optimization
+            CompileCache::from_env(&hash).unwrap_or_else(|err| {
+                log::warn!("Precompiling without the compile cache: {err:?}");
+                None
+            })
+        });
+        // Precompilation runs in the shim, which only knows the retention configured
+        // for the image and for the shim itself
+        let shim_retention = std::env::var(constants::SPIN_COMPONENTS_TO_RETAIN_ENV).ok();
+        let retained_digests = retained_layer_digests(layers, shim_retention.as_deref())
+            .unwrap_or_else(|err| {
+                // Skipping precompilation only saves work, which is not worth failing for
+                log::warn!("precompiling all components: {err:?}");
+                None
+            });
+        // Runwasi expects layers to be returned in the same order, so wrap each layer in an option, setting non Wasm layers and layers of components that are not retained to None
         let precompiled_layers = layers
             .iter()
             .map(|layer| match is_wasm_content(layer) {
+                Some(wasm_layer)
+                    if retained_digests.as_ref().is_some_and(|digests| {
+                        !digests.contains(&wasm_layer.config.digest().to_string())
+                    }) =>
+                {
+                    log::info!(
+                        "Skipping precompilation of layer {:?} of components that are not retained",
+                        wasm_layer.config.digest()
+                    );
+                    Ok(None)
+                }
                 Some(wasm_layer) => {
                     log::info!(
                         "Precompile called for wasm layer {:?}",
@@ -129,10 +207,8 @@ impl Engine for SpinEngine {
                         log::info!("Layer already precompiled {:?}", wasm_layer.config.digest());
                         Ok(Some(wasm_layer.layer))
                     } else {
-                        let component =
-                            spin_componentize::componentize_if_necessary(&wasm_layer.layer)?;
-                        let precompiled = self.wasmtime_engine.precompile_component(&component)?;
-                        Ok(Some(precompiled))
+                        self.precompile_layer(&wasm_layer, compile_cache.as_ref())
+                            .map(Some)
                     }
                 }
                 None => Ok(None),
@@ -150,107 +226,655 @@ impl Engine for SpinEngine {
     }
 }
 
-impl SpinEngine {
-    async fn wasm_exec_async(&self, ctx: &impl RuntimeContext) -> Result<()> {
-        let cache = initialize_cache().await?;
-        let app_source = Source::from_ctx(ctx, &cache).await?;
-        let mut locked_app = app_source.to_locked_app(&cache).await?;
-        if let Ok(components_env) = env::var(constants::SPIN_COMPONENTS_TO_RETAIN_ENV) {
-            let components = components_env
-                .split(',')
-                .filter(|s| !s.is_empty())
-                .collect::<Vec<&str>>();
-            locked_app = spin_app::retain_components(
-                locked_app,
-                &components,
-                &[&validate_service_chaining_for_components],
+/// Resolves the components whose layers are precompiled for an image to the
+/// digests of the Wasm layers they and their dependencies use.
+///
+/// These are the components selected by both the
+/// [`constants::SPIN_COMPONENTS_TO_RETAIN_ANNOTATION`] of the Spin application config
+/// layer and `shim_retention`, the [`constants::SPIN_COMPONENTS_TO_RETAIN_ENV`] of
+/// the shim, if any is set.
+fn retained_layer_digests(
+    layers: &[WasmLayer],
+    shim_retention: Option<&str>,
+) -> Result<Option<HashSet<String>>> {
+    let Some(config) = source::config_layer(layers) else {
+        return Ok(None);
+    };
+    let selections = [
+        source::components_to_retain(config).map(|spec| {
+            (
+                spec,
+                format!(
+                    "image annotation {}",
+                    constants::SPIN_COMPONENTS_TO_RETAIN_ANNOTATION
+                ),
             )
-            .with_context(|| {
+        }),
+        shim_retention.map(|spec| {
+            (
+                spec,
                 format!(
-                    "failed to resolve application with only [{components:?}] components retained by configured environment variable {}", constants::SPIN_COMPONENTS_TO_RETAIN_ENV
-                )
-            })?;
+                    "shim environment variable {}",
+                    constants::SPIN_COMPONENTS_TO_RETAIN_ENV
+                ),
+            )
+        }),
+    ];
+    if selections.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+    let locked_app = LockedApp::from_json(&config.layer)
+        .context("failed to parse spin application config layer")?;
+    let mut retained = locked_app
+        .components
+        .iter()
+        .map(|component| component.id.clone())
+        .collect::<HashSet<_>>();
+    for (spec, origin) in selections.iter().flatten() {
+        let selected = select_components(spec, origin, &locked_app)?;
+        retained.retain(|id| selected.contains(id));
+    }
+    let digests = locked_app
+        .components
+        .iter()
+        .filter(|component| retained.contains(&component.id))
+        .flat_map(|component| {
+            std::iter::once(&component.source)
+                .chain(component.dependencies.values().map(|dep| &dep.source))
+        })
+        .filter_map(|source| source.content.digest.clone())
+        .collect();
+    Ok(Some(digests))
+}
+
+/// Retains the components of the application selected by `spec`, which is
+/// configured by `origin`.
+fn retain_components(locked_app: LockedApp, spec: &str, origin: &str) -> Result<LockedApp> {
+    let components = select_components(spec, origin, &locked_app)?;
+    let components = components.iter().map(String::as_str).collect::<Vec<_>>();
+    let locked_app = spin_app::retain_components(
+        locked_app,
+        &components,
+        &[&validate_service_chaining_for_components],
+    )
+    .with_context(|| {
+        format!(
+            "failed to resolve application with only [{components:?}] components retained by {origin}"
+        )
+    })?;
+    info!(
+        " >>> retaining components {} ({origin})",
+        components.join(", ")
+    );
+    Ok(locked_app)
+}
+
+/// Compiles the components, and their dependencies, whose layers were not
+/// precompiled as the retention known to [`SpinEngine::precompile`] did not select
+/// them, so that they load next to the precompiled ones.
+///
+/// The artifacts are written to `dir`, which is created afresh and only accessible
+/// to the shim, and the sources of the components are rewritten to them.
+fn compile_skipped_components(
+    wasmtime_engine: &wasmtime::Engine,
+    locked_app: &mut LockedApp,
+    dir: &Path,
+) -> Result<()> {
+    let sources = locked_app.components.iter_mut().flat_map(|component| {
+        std::iter::once(&mut component.source).chain(
+            component
+                .dependencies
+                .values_mut()
+                .map(|dep| &mut dep.source),
+        )
+    });
+    let mut compiled = 0;
+    for source in sources {
+        let Some(url) = source.content.source.as_deref() else {
+            continue;
+        };
+        let path = spin_common::url::parse_file_url(url)?;
+        if wasmtime_engine.detect_precompiled_file(&path)?.is_some() {
+            continue;
         }
-        configure_application_variables_from_environment_variables(&locked_app)?;
-        let trigger_cmds = get_supported_triggers(&locked_app)
-            .with_context(|| format!("Couldn't find trigger executor for {app_source:?}"))?;
+        if compiled == 0 {
+            DirBuilder::new()
+                .mode(0o700)
+                .create(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let wasm = fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let component = spin_componentize::componentize_if_necessary(&wasm)?;
+        let precompiled = wasmtime_engine.precompile_component(&component)?;
+        let precompiled_path = dir.join(format!("{compiled}.cwasm"));
+        File::options()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&precompiled_path)
+            .and_then(|mut f| f.write_all(&precompiled))
+            .with_context(|| format!("failed to write {}", precompiled_path.display()))?;
+        source.content.source = Some(file_precompile::file_url(&precompiled_path)?);
+        compiled += 1;
+    }
+    if compiled > 0 {
+        info!(" >>> compiled {compiled} component(s) that were not precompiled for the image");
+    }
+    Ok(())
+}
+
+/// An application loaded and prepared for running.
+struct LoadedApp {
+    locked_app: LockedApp,
+    trigger_types: HashSet<String>,
+    /// Whether the components are AOT compiled
+    aot_compiled: bool,
+    state: AppState,
+}
+
+/// Marks errors that occurred while loading the application, before its triggers ran
+#[derive(Debug)]
+struct LoadFailure;
+
+impl fmt::Display for LoadFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to load Spin application")
+    }
+}
+
+/// Maps the error the application stopped with to the exit code of the container.
+///
+/// A guest exiting through WASI, such as a component of the command trigger
+/// calling `exit`, propagates its own exit status. Otherwise traps map to
+/// [`constants::EXIT_CODE_TRAP`], failures to load the application to
+/// [`constants::EXIT_CODE_LOAD_FAILURE`] and any other failure to
+/// [`constants::EXIT_CODE_FAILURE`].
+fn exit_code(err: &anyhow::Error) -> i32 {
+    if let Some(exit) = err.chain().find_map(|e| e.downcast_ref::<I32Exit>()) {
+        return exit.0;
+    }
+    if err.chain().any(|e| e.is::<wasmtime::Trap>()) {
+        return constants::EXIT_CODE_TRAP;
+    }
+    if err.is::<LoadFailure>() {
+        return constants::EXIT_CODE_LOAD_FAILURE;
+    }
+    constants::EXIT_CODE_FAILURE
+}
+
+/// Returns the components invoked by the triggers of the given type.
+fn trigger_components<'a>(
+    locked_app: &'a LockedApp,
+    trigger_type: &'a str,
+) -> impl Iterator<Item = &'a str> {
+    locked_app
+        .triggers
+        .iter()
+        .filter(move |trigger| trigger.trigger_type == trigger_type)
+        .filter_map(|trigger| trigger.trigger_config.get("component")?.as_str())
+}
+
+/// Telemetry resource attributes identifying the image the application was
+/// distributed with, as far as it is known.
+fn telemetry_attributes(image: Option<&OciImage>) -> Vec<(&'static str, &str)> {
+    let Some(image) = image else {
+        return Vec::new();
+    };
+    [
+        ("container.image.name", image.name()),
+        // The config digest is not the image id, which runtimes derive from the
+        // image config rather than the Spin application config
+        ("spin.app.config.digest", image.config_digest.as_deref()),
+        ("oci.manifest.digest", image.manifest_digest()),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key, value?)))
+    .collect()
+}
+
+/// Resolves once the container was signalled to stop.
+async fn stop_signalled(stop_signal: &mut watch::Receiver<bool>) {
+    if stop_signal.wait_for(|stop| *stop).await.is_err() {
+        future::pending().await
+    }
+}
+
+/// Waits up to `drain_period` for the invocations in flight to complete, logging
+/// how many were cut off. The `running` triggers keep running meanwhile, as they
+/// may be running invocations themselves, but no longer start new ones.
+async fn drain(
+    mut running: FuturesUnordered<LocalBoxFuture<'_, (String, Result<()>)>>,
+    invocations: &Invocations,
+    drain_period: Duration,
+) {
+    info!(
+        "draining in-flight invocations for up to {}s",
+        drain_period.as_secs()
+    );
+    let drained = invocations.drain(drain_period);
+    let triggers = async {
+        while running.next().await.is_some() {}
+        future::pending().await
+    };
+    let remaining = tokio::select! {
+        remaining = drained => remaining,
+        never = triggers => never,
+    };
+    match remaining {
+        0 if invocations.has_untracked() => info!(
+            "shutting down after a drain period of {}s, as invocations of WAGI components are not tracked",
+            drain_period.as_secs()
+        ),
+        0 => info!("all in-flight invocations completed before shutdown"),
+        remaining => log::warn!(
+            "shutting down with {remaining} in-flight invocation(s) cut off after a drain period of {}s",
+            drain_period.as_secs()
+        ),
+    }
+}
+
+/// Wraps the future of a running trigger so that it can be dropped while draining,
+/// registering its [`AbortHandle`]. An aborted trigger is reported as exited.
+fn abortable_trigger<'a>(
+    trigger_type: String,
+    f: impl Future<Output = Result<()>> + 'a,
+    abort_handles: &mut HashMap<String, AbortHandle>,
+) -> LocalBoxFuture<'a, (String, Result<()>)> {
+    let (f, abort_handle) = future::abortable(f);
+    abort_handles.insert(trigger_type.clone(), abort_handle);
+    async move { (trigger_type, f.await.unwrap_or(Ok(()))) }.boxed_local()
+}
+
+impl SpinEngine {
+    /// Compiles a Wasm layer, reusing the artifact from the node-wide compile
+    /// cache when one was produced for the same layer digest and engine.
+    fn precompile_layer(
+        &self,
+        wasm_layer: &WasmLayer,
+        compile_cache: Option<&CompileCache>,
+    ) -> Result<Vec<u8>> {
+        let digest = wasm_layer.config.digest().to_string();
+        if let Some(cache) = compile_cache {
+            match cache.get(&digest) {
+                Some(precompiled)
+                    if self.wasmtime_engine.detect_precompiled(&precompiled)
+                        == Some(wasmtime::Precompiled::Component) =>
+                {
+                    log::info!("Using cached precompiled component for layer {digest}");
+                    return Ok(precompiled);
+                }
+                Some(_) => {
+                    log::warn!(
+                        "Discarding invalid cached precompiled component for layer {digest}"
+                    );
+                    cache.remove(&digest);
+                }
+                None => {}
+            }
+        }
+
+        let component = spin_componentize::componentize_if_necessary(&wasm_layer.layer)?;
+        let precompiled = self.wasmtime_engine.precompile_component(&component)?;
+        if let Some(cache) = compile_cache {
+            // A failure to cache should not fail the precompilation itself
+            if let Err(err) = cache.put(&digest, &precompiled) {
+                log::warn!("Failed to cache precompiled component for layer {digest}: {err:?}");
+            }
+        }
+        Ok(precompiled)
+    }
+
+    /// Precompiles the components of an application loaded from a file manifest
+    /// if [`constants::SPIN_PRECOMPILE_DIR_ENV`] is set, returning whether they
+    /// can be loaded AOT compiled.
+    fn precompile_file_components(
+        &self,
+        env: &ContainerEnv,
+        locked_app: &mut LockedApp,
+    ) -> Result<bool> {
+        let Some(dir) = env.var(constants::SPIN_PRECOMPILE_DIR_ENV) else {
+            return Ok(false);
+        };
+        let (Some(key), Some(compatibility_hash)) = (&self.artifact_key, self.can_precompile())
+        else {
+            return Ok(false);
+        };
+        let cache = match CompileCache::open(dir, &compatibility_hash) {
+            Ok(cache) => cache,
+            Err(err) => {
+                log::warn!("not precompiling application: {err:?}");
+                return Ok(false);
+            }
+        };
+        file_precompile::precompile_components(
+            &self.wasmtime_engine,
+            key,
+            &cache,
+            locked_app,
+            Path::new(constants::SPIN_PRECOMPILED_COMPONENTS_DIR),
+        )
+    }
+
+    async fn wasm_exec_async(
+        &self,
+        ctx: &impl RuntimeContext,
+        env: &ContainerEnv,
+        mut stop_signal: watch::Receiver<bool>,
+        drain_period: Duration,
+    ) -> Result<()> {
+        let health = Arc::new(HealthState::default());
+        let _health_server = match env.var(constants::SPIN_HEALTH_LISTEN_ADDR_ENV) {
+            Some(address_str) => {
+                Some(health::serve(parse_addr(address_str)?, health.clone()).await?)
+            }
+            None => None,
+        };
+        let (metrics, _metrics_server) = match env.var(constants::SPIN_METRICS_LISTEN_ADDR_ENV) {
+            Some(address_str) => {
+                let metrics = Arc::new(Metrics::default());
+                let server = metrics::serve(parse_addr(address_str)?, metrics.clone()).await?;
+                (Some(metrics), Some(server))
+            }
+            None => (None, None),
+        };
+        let registry = TriggerRegistry::default();
+        let app = tokio::select! {
+            loaded = self.load_app(ctx, env, &registry, &health, metrics) => loaded.context(LoadFailure)?,
+            _ = stop_signalled(&mut stop_signal) => {
+                info!("Received signal to stop while loading the application");
+                return Ok(());
+            }
+        };
         let _telemetry_guard = spin_telemetry::init(version!().to_string())?;
 
-        self.run_trigger(ctx, &trigger_cmds, locked_app, app_source)
-            .await
+        Self::run_trigger(ctx, env, &registry, app, &health, stop_signal, drain_period).await
     }
 
-    async fn run_trigger(
+    /// Loads the application and prepares it for running, recording its
+    /// invocations in `metrics` if they are collected.
+    async fn load_app(
         &self,
         ctx: &impl RuntimeContext,
-        trigger_types: &HashSet<String>,
-        app: LockedApp,
-        app_source: Source,
+        env: &ContainerEnv,
+        registry: &TriggerRegistry,
+        health: &HealthState,
+        metrics: Option<Arc<Metrics>>,
+    ) -> Result<LoadedApp> {
+        let cache = initialize_cache().await?;
+        let verifier = self.signature_verifier.for_container(env)?;
+        let app_source =
+            Source::from_ctx(ctx, env, &cache, &self.wasmtime_engine, &verifier).await?;
+        let mut locked_app = app_source.to_locked_app(env, &cache).await?;
+        health.set_live();
+        if let Some(spec) = env.var(constants::SPIN_COMPONENTS_TO_RETAIN_ENV) {
+            locked_app = retain_components(
+                locked_app,
+                spec,
+                &format!(
+                    "configured environment variable {}",
+                    constants::SPIN_COMPONENTS_TO_RETAIN_ENV
+                ),
+            )?;
+        }
+        let aot_compiled = match &app_source {
+            Source::Oci(_) => {
+                compile_skipped_components(
+                    &self.wasmtime_engine,
+                    &mut locked_app,
+                    Path::new(constants::SPIN_PRECOMPILED_COMPONENTS_DIR),
+                )?;
+                true
+            }
+            Source::File(_) => self.precompile_file_components(env, &mut locked_app)?,
+        };
+        if let Some(metrics) = &metrics {
+            metrics.register_app(&locked_app);
+        }
+        let limits = InstanceLimits::from_env(env, &locked_app)?;
+        limits.log();
+        let state = AppState {
+            invocations: Arc::new(Invocations::new(metrics.clone())),
+            limits: Arc::new(limits),
+            metrics,
+            runtime_config: Arc::new(ResolvedRuntimeConfig::resolve(env)?),
+        };
+        if runtime_config::configures_variables_providers()? {
+            info!(" >>> skipping validation of required variables configured by runtime config providers");
+        } else {
+            validate_required_variables(
+                env,
+                &locked_app,
+                FileVariablesProvider::from_env(env)?.as_ref(),
+            )?;
+        }
+        let trigger_cmds = registry
+            .supported_triggers(&locked_app, env.var(constants::SPIN_TRIGGERS_TO_RUN_ENV))
+            .with_context(|| format!("Couldn't find trigger executor for {app_source:?}"))?;
+        if let Source::Oci(image) = &app_source {
+            info!(
+                " >>> running spin application from image {} (config digest {})",
+                image
+                    .reference
+                    .as_deref()
+                    .unwrap_or("with unknown reference"),
+                image.config_digest.as_deref().unwrap_or("unknown")
+            );
+        }
+        Ok(LoadedApp {
+            locked_app,
+            trigger_types: trigger_cmds,
+            aot_compiled,
+            state,
+        })
+    }
+
+    async fn run_trigger(
+        ctx: &impl RuntimeContext,
+        env: &ContainerEnv,
+        registry: &TriggerRegistry,
+        loaded: LoadedApp,
+        health: &HealthState,
+        mut stop_signal: watch::Receiver<bool>,
+        drain_period: Duration,
     ) -> Result<()> {
-        let mut loader = spin_trigger::loader::ComponentLoader::default();
-        match app_source {
-            Source::Oci => unsafe {
-                // Configure the loader to support loading AOT compiled components..
-                // Since all components were compiled by the shim (during `precompile`),
-                // this operation can be considered safe.
+        let LoadedApp {
+            locked_app: app,
+            trigger_types,
+            aot_compiled,
+            state,
+        } = loaded;
+        let mut loader = ComponentLoader::default();
+        if aot_compiled {
+            // Configure the loader to support loading AOT compiled components.
+            // Since all components were compiled by the shim, either during `precompile`
+            // or into a directory only the shim writes to after verifying they were
+            // compiled by this shim, this operation can be considered safe.
+            unsafe {
                 loader.enable_loading_aot_compiled_components();
-            },
-            // Currently, it is only possible to precompile applications distributed using
-            // `spin registry push`
-            Source::File(_) => {}
+            }
+        }
+        let allocator = AllocatorMode::from_env(env)?.or_fallback();
+        let args = TriggerArgs {
+            guest_args: ctx.args(),
+            env,
+            loader: &loader,
+            allocator: &allocator,
+            state: &state,
         };
 
-        let mut futures_list = Vec::new();
-        let mut trigger_type_map = Vec::new();
+        let restart_policies = match env.var(constants::SPIN_TRIGGER_RESTART_POLICY_ENV) {
+            Some(spec) => {
+                let app_trigger_types = app
+                    .triggers
+                    .iter()
+                    .map(|trigger| trigger.trigger_type.clone())
+                    .collect();
+                parse_restart_policies(spec, &app_trigger_types, &trigger_types)?
+            }
+            None => HashMap::new(),
+        };
+
+        for trigger_type in trigger_types.iter() {
+            health.set_trigger_status(trigger_type, TriggerStatus::Pending);
+        }
+        let mut running = FuturesUnordered::new();
+        let mut abort_handles = HashMap::new();
         // The `HOSTNAME` environment variable should contain the fully unique container name
-        let app_id = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".into());
+        let app_id = env.var("HOSTNAME").unwrap_or("unknown");
         for trigger_type in trigger_types.iter() {
-            let app = spin_app::App::new(&app_id, app.clone());
-            let f = match trigger_type.as_str() {
-                HTTP_TRIGGER_TYPE => {
-                    let address_str = env::var(constants::SPIN_HTTP_LISTEN_ADDR_ENV)
-                        .unwrap_or_else(|_| constants::SPIN_ADDR_DEFAULT.to_string());
-                    let address = parse_addr(&address_str)?;
-                    let cli_args = spin_trigger_http::CliArgs {
-                        address,
-                        tls_cert: None,
-                        tls_key: None,
-                    };
-                    trigger::run::<HttpTrigger>(cli_args, app, &loader).await?
-                }
-                REDIS_TRIGGER_TYPE => trigger::run::<RedisTrigger>(NoCliArgs, app, &loader).await?,
-                SQS_TRIGGER_TYPE => trigger::run::<SqsTrigger>(NoCliArgs, app, &loader).await?,
-                COMMAND_TRIGGER_TYPE => {
-                    let cli_args = trigger_command::CliArgs {
-                        guest_args: ctx.args().to_vec(),
-                    };
-                    trigger::run::<CommandTrigger>(cli_args, app, &loader).await?
-                }
-                MQTT_TRIGGER_TYPE => {
-                    let cli_args = trigger_mqtt::CliArgs { test: false };
-                    trigger::run::<MqttTrigger>(cli_args, app, &loader).await?
-                }
-                _ => {
-                    // This should never happen as we check for supported triggers in get_supported_triggers
-                    unreachable!()
-                }
-            };
-
-            trigger_type_map.push(trigger_type.clone());
-            futures_list.push(f);
+            let app = spin_app::App::new(app_id, app.clone());
+            // Only failures to construct the triggers initially are load failures, a
+            // trigger failing to restart is a failure of the running application
+            let f = Self::start_trigger(registry, trigger_type, &args, app, health)
+                .await
+                .context(LoadFailure)?;
+            running.push(abortable_trigger(
+                trigger_type.clone(),
+                f,
+                &mut abort_handles,
+            ));
         }
 
         info!(" >>> notifying main thread we are about to start");
 
-        // exit as soon as any of the trigger completes/exits
-        let (result, index, rest) = future::select_all(futures_list).await;
-        let trigger_type = &trigger_type_map[index];
+        let mut restarts: HashMap<String, u32> = HashMap::new();
+        let mut running_since: HashMap<String, Instant> = trigger_types
+            .iter()
+            .map(|trigger_type| (trigger_type.clone(), Instant::now()))
+            .collect();
+        loop {
+            // wait for any of the triggers to complete/exit and apply its restart policy
+            let (trigger_type, result) = tokio::select! {
+                Some(exited) = running.next() => exited,
+                _ = stop_signalled(&mut stop_signal) => {
+                    info!("Received signal to stop");
+                    // HTTP triggers stop accepting connections when dropped, while the
+                    // requests in flight run on tasks of their own. Other triggers run
+                    // their invocations inline and keep running while draining.
+                    if let Some(http) = abort_handles.get("http") {
+                        http.abort();
+                    }
+                    drain(running, &state.invocations, drain_period).await;
+                    return Ok(());
+                }
+            };
 
-        info!(" >>> trigger type '{trigger_type}' exited");
+            info!(trigger = trigger_type.as_str(); " >>> trigger type '{trigger_type}' exited");
+            if let Some(metrics) = &state.metrics {
+                metrics.record_trigger_exit(&trigger_type, &result);
+                // The command trigger exits with the single invocation of its component
+                if trigger_type == "command"
+                    && result.as_ref().is_err_and(|err| exit_code(err) != 0)
+                {
+                    for component in trigger_components(&app, &trigger_type) {
+                        metrics.record_invocation_error(component);
+                    }
+                }
+            }
 
-        drop(rest);
+            match restart_policies
+                .get(&trigger_type)
+                .copied()
+                .unwrap_or_default()
+            {
+                RestartPolicy::ExitAll => {
+                    health.set_trigger_status(&trigger_type, TriggerStatus::Exited);
+                    return result;
+                }
+                RestartPolicy::Ignore => {
+                    health.set_trigger_status(&trigger_type, TriggerStatus::Exited);
+                    if let Err(err) = &result {
+                        log::warn!(trigger = trigger_type.as_str(); "ignoring failure of trigger type '{trigger_type}': {err:?}");
+                    }
+                    if running.is_empty() {
+                        return Ok(());
+                    }
+                }
+                RestartPolicy::Restart => {
+                    if let Err(err) = &result {
+                        log::error!(trigger = trigger_type.as_str(); "trigger type '{trigger_type}' failed: {err:?}");
+                    }
+                    health.set_trigger_status(&trigger_type, TriggerStatus::Restarting);
+                    // A trigger that kept running for a while is restarted as if it
+                    // never failed before
+                    let stable = Duration::from_secs(constants::TRIGGER_RESTART_RESET_SECS);
+                    if running_since
+                        .get(&trigger_type)
+                        .is_some_and(|since| since.elapsed() >= stable)
+                    {
+                        restarts.remove(&trigger_type);
+                    }
+                    let count = {
+                        let count = restarts.entry(trigger_type.clone()).or_default();
+                        *count += 1;
+                        *count
+                    };
+                    let backoff = restart_backoff(count);
+                    log::warn!(
+                        trigger = trigger_type.as_str();
+                        "restarting trigger type '{trigger_type}' in {}s (restart {count})",
+                        backoff.as_secs()
+                    );
+                    if let Some(metrics) = &state.metrics {
+                        metrics.record_trigger_restart(&trigger_type);
+                    }
+                    running_since.insert(trigger_type.clone(), Instant::now() + backoff);
+                    let app = spin_app::App::new(app_id, app.clone());
+                    let args = &args;
+                    let restart = {
+                        let trigger_type = trigger_type.clone();
+                        async move {
+                            tokio::time::sleep(backoff).await;
+                            if args.state.invocations.is_draining() {
+                                return Ok(());
+                            }
+                            Self::start_trigger(registry, &trigger_type, args, app, health)
+                                .await?
+                                .await
+                        }
+                    };
+                    running.push(abortable_trigger(trigger_type, restart, &mut abort_handles));
+                }
+            }
+        }
+    }
 
-        result
+    /// Constructs a trigger, returning the future that runs it. The trigger is
+    /// recorded as ready in the health state and metrics once it runs and, if it
+    /// listens on an address, accepts connections.
+    async fn start_trigger<'a>(
+        registry: &TriggerRegistry,
+        trigger_type: &'a str,
+        args: &TriggerArgs<'_>,
+        app: spin_app::App,
+        health: &'a HealthState,
+    ) -> Result<LocalBoxFuture<'a, Result<()>>> {
+        let started = Instant::now();
+        let metrics = args.state.metrics.clone();
+        let StartedTrigger {
+            future,
+            listen_addr,
+        } = registry
+            .start(trigger_type, args, app)
+            .await
+            .inspect_err(|_| health.set_trigger_status(trigger_type, TriggerStatus::Failed))?;
+        let ready = async move {
+            if let Some(addr) = listen_addr {
+                server::wait_until_listening(addr).await;
+            }
+            health.set_trigger_status(trigger_type, TriggerStatus::Ready);
+            if let Some(metrics) = &metrics {
+                metrics.record_trigger_started(trigger_type, started.elapsed());
+            }
+            future::pending().await
+        };
+        Ok(async move {
+            tokio::select! {
+                result = future => result,
+                never = ready => never,
+            }
+        }
+        .boxed_local())
     }
 }
 
@@ -258,10 +882,54 @@ impl SpinEngine {
 mod tests {
     use std::str::FromStr as _;
 
-    use oci_spec::image::{Digest, MediaType};
+    use oci_spec::image::{Descriptor, Digest, MediaType};
 
     use super::*;
 
+    #[test]
+    fn maps_errors_to_exit_codes() {
+        let err = anyhow::Error::new(I32Exit(3)).context("command trigger failed");
+        assert_eq!(exit_code(&err), 3);
+        let err = anyhow::Error::new(I32Exit(0));
+        assert_eq!(exit_code(&err), 0);
+
+        let err = anyhow::Error::new(wasmtime::Trap::UnreachableCodeReached)
+            .context("error while executing at wasm backtrace");
+        assert_eq!(exit_code(&err), constants::EXIT_CODE_TRAP);
+
+        let err = anyhow::anyhow!("no such file").context(LoadFailure);
+        assert_eq!(exit_code(&err), constants::EXIT_CODE_LOAD_FAILURE);
+        // A trap while a trigger is being constructed is still reported as a trap
+        let err = anyhow::Error::new(wasmtime::Trap::StackOverflow).context(LoadFailure);
+        assert_eq!(exit_code(&err), constants::EXIT_CODE_TRAP);
+
+        let err = anyhow::anyhow!("connection refused");
+        assert_eq!(exit_code(&err), constants::EXIT_CODE_FAILURE);
+    }
+
+    #[test]
+    fn omits_unknown_telemetry_attributes() {
+        let image = OciImage {
+            reference: None,
+            config_digest: Some(digest('f')),
+        };
+        assert_eq!(
+            telemetry_attributes(Some(&image)),
+            vec![("spin.app.config.digest", digest('f').as_str())]
+        );
+        let image = OciImage {
+            reference: Some("ghcr.io/spinkube/hello:v1".to_string()),
+            ..image
+        };
+        assert_eq!(
+            telemetry_attributes(Some(&image)),
+            vec![
+                ("container.image.name", "ghcr.io/spinkube/hello:v1"),
+                ("spin.app.config.digest", digest('f').as_str()),
+            ]
+        );
+    }
+
     #[test]
     fn precompile() {
         let module = wat::parse_str("(module)").unwrap();
@@ -320,4 +988,219 @@ mod tests {
         );
         assert!(precompiled[2].is_none());
     }
+
+    #[test]
+    fn precompiles_without_a_cache_that_fails_to_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let layers = vec![layer(
+            constants::OCI_LAYER_MEDIA_TYPE_WASM,
+            wat::parse_str("(module)").unwrap(),
+            digest('a'),
+        )];
+        temp_env::with_vars(
+            [
+                (
+                    constants::SPIN_PRECOMPILE_CACHE_DIR_ENV,
+                    Some(dir.path().to_str().unwrap()),
+                ),
+                (constants::SPIN_PRECOMPILE_CACHE_MAX_SIZE_ENV, Some("lots")),
+            ],
+            || {
+                let precompiled = SpinEngine::default().precompile(&layers).unwrap();
+                assert!(precompiled[0].is_some());
+            },
+        );
+        assert_eq!(dir.path().read_dir().unwrap().count(), 0);
+    }
+
+    /// Layers of an application with an `api` component and a `worker` component
+    /// depending on a library, whose config layer retains `spec`.
+    fn retention_layers(spec: Option<&str>) -> Vec<WasmLayer> {
+        let app_json = format!(
+            r#"
+            {{
+                "spin_lock_version": 1,
+                "components": [
+                    {{ "id": "api", "source": {{ "content_type": "application/wasm", "digest": "{}" }} }},
+                    {{ "id": "worker", "source": {{ "content_type": "application/wasm", "digest": "{}" }},
+                       "dependencies": {{ "my:dep/lib": {{ "source": {{ "content_type": "application/wasm", "digest": "{}" }} }} }} }}
+                ],
+                "triggers": []
+            }}"#,
+            digest('a'),
+            digest('b'),
+            digest('c')
+        );
+        let module = wat::parse_str("(module)").unwrap();
+        let mut config = layer(
+            spin_oci::client::SPIN_APPLICATION_MEDIA_TYPE,
+            app_json.into_bytes(),
+            digest('f'),
+        );
+        config.config.set_annotations(spec.map(|spec| {
+            HashMap::from([(
+                constants::SPIN_COMPONENTS_TO_RETAIN_ANNOTATION.to_string(),
+                spec.to_string(),
+            )])
+        }));
+        vec![
+            config,
+            layer(
+                constants::OCI_LAYER_MEDIA_TYPE_WASM,
+                module.clone(),
+                digest('a'),
+            ),
+            layer(
+                constants::OCI_LAYER_MEDIA_TYPE_WASM,
+                module.clone(),
+                digest('b'),
+            ),
+            layer(constants::OCI_LAYER_MEDIA_TYPE_WASM, module, digest('c')),
+        ]
+    }
+
+    fn digest(c: char) -> String {
+        format!("sha256:{}", c.to_string().repeat(64))
+    }
+
+    fn layer(media_type: &str, layer: Vec<u8>, digest: String) -> WasmLayer {
+        WasmLayer {
+            layer,
+            config: Descriptor::new(
+                MediaType::Other(media_type.to_string()),
+                1024,
+                Digest::from_str(&digest).unwrap(),
+            ),
+        }
+    }
+
+    #[test]
+    fn precompiles_only_layers_of_retained_components() {
+        let spin_engine = SpinEngine::default();
+        temp_env::with_var_unset(constants::SPIN_COMPONENTS_TO_RETAIN_ENV, || {
+            let precompiled = spin_engine
+                .precompile(&retention_layers(Some("worker")))
+                .unwrap();
+            assert_eq!(precompiled.len(), 4);
+            assert!(precompiled[0].is_none());
+            assert!(precompiled[1].is_none());
+            for layer in &precompiled[2..] {
+                let layer = layer.as_deref().expect("retained layer not precompiled");
+                assert!(spin_engine
+                    .wasmtime_engine
+                    .detect_precompiled(layer)
+                    .is_some());
+            }
+
+            let precompiled = spin_engine.precompile(&retention_layers(None)).unwrap();
+            assert!(precompiled[1..].iter().all(Option::is_some));
+            // Invalid retention precompiles all components
+            let precompiled = spin_engine
+                .precompile(&retention_layers(Some("billing")))
+                .unwrap();
+            assert!(precompiled[1..].iter().all(Option::is_some));
+        });
+
+        temp_env::with_var(
+            constants::SPIN_COMPONENTS_TO_RETAIN_ENV,
+            Some("worker"),
+            || {
+                let precompiled = spin_engine.precompile(&retention_layers(None)).unwrap();
+                assert!(precompiled[1].is_none());
+                assert!(precompiled[2..].iter().all(Option::is_some));
+            },
+        );
+    }
+
+    #[test]
+    fn resolves_retained_layer_digests() {
+        let retained =
+            |annotation, shim| retained_layer_digests(&retention_layers(annotation), shim);
+        assert_eq!(
+            retained(Some("api"), None).unwrap(),
+            Some(HashSet::from([digest('a')]))
+        );
+        assert_eq!(
+            retained(Some("!api"), None).unwrap(),
+            Some(HashSet::from([digest('b'), digest('c')]))
+        );
+        assert_eq!(
+            retained(None, Some("api")).unwrap(),
+            Some(HashSet::from([digest('a')]))
+        );
+        // Both the image and the shim must retain a component
+        assert_eq!(
+            retained(Some("api"), Some("worker")).unwrap(),
+            Some(HashSet::new())
+        );
+        assert_eq!(retained(None, None).unwrap(), None);
+        assert_eq!(
+            retained_layer_digests(&retention_layers(Some("api"))[1..], Some("api")).unwrap(),
+            None
+        );
+
+        let err = retained(Some("billing"), None).unwrap_err().to_string();
+        assert!(
+            err.contains(constants::SPIN_COMPONENTS_TO_RETAIN_ANNOTATION),
+            "{err}"
+        );
+        let err = retained(None, Some("billing")).unwrap_err().to_string();
+        assert!(
+            err.contains("shim environment variable SPIN_COMPONENTS_TO_RETAIN"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn compiles_components_that_were_not_precompiled() {
+        let wasmtime_engine = SpinEngine::default().wasmtime_engine;
+        let dir = tempfile::tempdir().unwrap();
+        let component = wat::parse_str("(component)").unwrap();
+        let precompiled_path = dir.path().join("api.cwasm");
+        fs::write(
+            &precompiled_path,
+            wasmtime_engine.precompile_component(&component).unwrap(),
+        )
+        .unwrap();
+        let wasm_path = dir.path().join("worker.wasm");
+        fs::write(&wasm_path, &component).unwrap();
+        let source = |path: &Path| url::Url::from_file_path(path).unwrap().to_string();
+        let app_json = format!(
+            r#"{{
+                "spin_lock_version": 1,
+                "components": [
+                    {{ "id": "api", "source": {{ "content_type": "application/wasm", "source": "{}" }} }},
+                    {{ "id": "worker", "source": {{ "content_type": "application/wasm", "source": "{}" }} }}
+                ],
+                "triggers": []
+            }}"#,
+            source(&precompiled_path),
+            source(&wasm_path)
+        );
+        let mut locked_app = LockedApp::from_json(app_json.as_bytes()).unwrap();
+
+        let out_dir = dir.path().join("precompiled");
+        compile_skipped_components(&wasmtime_engine, &mut locked_app, &out_dir).unwrap();
+        let sources = locked_app
+            .components
+            .iter()
+            .map(|c| {
+                spin_common::url::parse_file_url(c.source.content.source.as_deref().unwrap())
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sources[0], precompiled_path);
+        assert!(sources[1].starts_with(&out_dir));
+        assert_eq!(
+            wasmtime_engine
+                .detect_precompiled_file(&sources[1])
+                .unwrap(),
+            Some(wasmtime::Precompiled::Component)
+        );
+
+        // Nothing is written when all components were precompiled
+        let out_dir = dir.path().join("unused");
+        compile_skipped_components(&wasmtime_engine, &mut locked_app, &out_dir).unwrap();
+        assert!(!out_dir.exists());
+    }
 }
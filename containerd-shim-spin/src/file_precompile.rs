@@ -0,0 +1,278 @@
+use std::{
+    fs::{self, DirBuilder, File},
+    io::{Read, Write},
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use log::info;
+use sha2::{Digest as _, Sha256};
+use spin_app::locked::LockedApp;
+
+use crate::compile_cache::CompileCache;
+
+/// Length of the MAC each sealed artifact starts with.
+const MAC_LEN: usize = 32;
+
+/// Key authenticating the components the shim precompiled for applications loaded
+/// from a file manifest.
+///
+/// These artifacts are stored in a volume of the pod, which the containers of the
+/// pod may write to, so each one is sealed with a MAC under this key. The key is
+/// generated when the shim starts and only ever held in its memory, so only
+/// artifacts produced by the same shim, which runs the containers of a single pod,
+/// are loaded as native code. They are reused when containers restart, while a
+/// new pod compiles its components again.
+#[derive(Clone)]
+pub(crate) struct ArtifactKey([u8; MAC_LEN]);
+
+impl ArtifactKey {
+    pub(crate) fn generate() -> Result<Self> {
+        let mut key = [0; MAC_LEN];
+        File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(&mut key))
+            .context("failed to generate precompiled artifact key")?;
+        Ok(Self(key))
+    }
+
+    fn mac(&self, digest: &str, compiled: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(digest.as_bytes());
+        mac.update(&[0]);
+        mac.update(compiled);
+        mac
+    }
+
+    /// Seals the artifact compiled from the Wasm with the given digest.
+    fn seal(&self, digest: &str, compiled: &[u8]) -> Vec<u8> {
+        let tag = self.mac(digest, compiled).finalize().into_bytes();
+        [tag.as_slice(), compiled].concat()
+    }
+
+    /// Returns the artifact compiled from the Wasm with the given digest if it was
+    /// sealed with this key.
+    fn unseal<'a>(&self, digest: &str, sealed: &'a [u8]) -> Option<&'a [u8]> {
+        if sealed.len() < MAC_LEN {
+            return None;
+        }
+        let (tag, compiled) = sealed.split_at(MAC_LEN);
+        self.mac(digest, compiled).verify_slice(tag).ok()?;
+        Some(compiled)
+    }
+}
+
+/// Precompiles the components of an application loaded from a file manifest,
+/// reusing the artifacts sealed with `key` in `cache`.
+///
+/// The artifacts are verified in memory and written to `dir`, which is created
+/// afresh and only accessible to the shim, so they cannot be replaced once
+/// verified. The sources of the components are then rewritten to them. Returns
+/// whether the components can be loaded AOT compiled.
+pub(crate) fn precompile_components(
+    wasmtime_engine: &wasmtime::Engine,
+    key: &ArtifactKey,
+    cache: &CompileCache,
+    locked_app: &mut LockedApp,
+    dir: &Path,
+) -> Result<bool> {
+    // Components with dependencies are composed at load time, which is not
+    // possible for precompiled components.
+    if let Some(component) = locked_app
+        .components
+        .iter()
+        .find(|c| !c.dependencies.is_empty())
+    {
+        info!(
+            "not precompiling application: component {} has dependencies",
+            component.id
+        );
+        return Ok(false);
+    }
+
+    if let Err(err) = DirBuilder::new().mode(0o700).create(dir) {
+        log::warn!(
+            "not precompiling application: failed to create {}: {err}",
+            dir.display()
+        );
+        return Ok(false);
+    }
+
+    let mut precompiled_paths = Vec::with_capacity(locked_app.components.len());
+    for (index, component) in locked_app.components.iter().enumerate() {
+        let source = component
+            .source
+            .content
+            .source
+            .as_deref()
+            .with_context(|| format!("component {} has no source", component.id))?;
+        let wasm_path = spin_common::url::parse_file_url(source)?;
+        let wasm = fs::read(&wasm_path)
+            .with_context(|| format!("failed to read {}", wasm_path.display()))?;
+        let digest = format!("sha256:{:x}", Sha256::digest(&wasm));
+
+        let sealed = cache.get(&digest);
+        let cached = sealed
+            .as_deref()
+            .and_then(|sealed| key.unseal(&digest, sealed))
+            .filter(|compiled| {
+                wasmtime_engine.detect_precompiled(compiled)
+                    == Some(wasmtime::Precompiled::Component)
+            });
+        let compiled = match cached {
+            Some(compiled) => {
+                info!("using precompiled component {}", component.id);
+                compiled.to_vec()
+            }
+            None => {
+                if sealed.is_some() {
+                    log::warn!(
+                        "discarding precompiled component {} that was not produced by this shim",
+                        component.id
+                    );
+                    cache.remove(&digest);
+                }
+                info!("precompiling component {}", component.id);
+                let component = spin_componentize::componentize_if_necessary(&wasm)?;
+                let compiled = wasmtime_engine.precompile_component(&component)?;
+                // A failure to cache should not fail loading the application
+                if let Err(err) = cache.put(&digest, &key.seal(&digest, &compiled)) {
+                    log::warn!("failed to cache precompiled component: {err:?}");
+                }
+                compiled
+            }
+        };
+
+        let path = dir.join(format!("{index}.cwasm"));
+        File::options()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut f| f.write_all(&compiled))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        precompiled_paths.push(path);
+    }
+
+    for (component, path) in locked_app.components.iter_mut().zip(precompiled_paths) {
+        component.source.content.source = Some(file_url(&path)?);
+    }
+    Ok(true)
+}
+
+pub(crate) fn file_url(path: &Path) -> Result<String> {
+    let url = url::Url::from_file_path(path)
+        .map_err(|_| anyhow::anyhow!("invalid precompiled path {}", path.display()))?;
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    struct App {
+        dir: tempfile::TempDir,
+        locked_app: LockedApp,
+    }
+
+    fn app() -> App {
+        let dir = tempfile::tempdir().unwrap();
+        let wasm_path = dir.path().join("hello.wasm");
+        fs::write(&wasm_path, wat::parse_str("(component)").unwrap()).unwrap();
+        let source = url::Url::from_file_path(&wasm_path).unwrap();
+        let app_json = format!(
+            r#"{{
+                "spin_lock_version": 1,
+                "components": [{{
+                    "id": "hello",
+                    "source": {{ "content_type": "application/wasm", "source": "{source}" }}
+                }}],
+                "triggers": []
+            }}"#
+        );
+        let locked_app = LockedApp::from_json(app_json.as_bytes()).unwrap();
+        App { dir, locked_app }
+    }
+
+    fn precompiled_source(locked_app: &LockedApp) -> PathBuf {
+        let source = locked_app.components[0].source.content.source.as_deref();
+        spin_common::url::parse_file_url(source.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn precompiles_components() {
+        let wasmtime_engine = wasmtime::Engine::default();
+        let key = ArtifactKey::generate().unwrap();
+        let App {
+            dir,
+            mut locked_app,
+        } = app();
+        let cache = CompileCache::new(dir.path().join("cache"), "1234", u64::MAX).unwrap();
+
+        let out_dir = dir.path().join("precompiled");
+        assert!(
+            precompile_components(&wasmtime_engine, &key, &cache, &mut locked_app, &out_dir)
+                .unwrap()
+        );
+        let path = precompiled_source(&locked_app);
+        assert!(path.starts_with(&out_dir));
+        assert_eq!(
+            wasmtime_engine.detect_precompiled_file(path).unwrap(),
+            Some(wasmtime::Precompiled::Component)
+        );
+
+        // The directory must not exist yet, so that it was never writable by others
+        let mut locked_app = app().locked_app;
+        assert!(
+            !precompile_components(&wasmtime_engine, &key, &cache, &mut locked_app, &out_dir)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_artifacts_not_sealed_with_the_key() {
+        let wasmtime_engine = wasmtime::Engine::default();
+        let key = ArtifactKey::generate().unwrap();
+        let App {
+            dir,
+            mut locked_app,
+        } = app();
+        let cache = CompileCache::new(dir.path().join("cache"), "1234", u64::MAX).unwrap();
+        let wasm = fs::read(dir.path().join("hello.wasm")).unwrap();
+        let digest = format!("sha256:{:x}", Sha256::digest(&wasm));
+        let planted = wasmtime_engine
+            .precompile_component(&wat::parse_str("(component (core module))").unwrap())
+            .unwrap();
+        cache
+            .put(
+                &digest,
+                &ArtifactKey::generate().unwrap().seal(&digest, &planted),
+            )
+            .unwrap();
+
+        let out_dir = dir.path().join("precompiled");
+        assert!(
+            precompile_components(&wasmtime_engine, &key, &cache, &mut locked_app, &out_dir)
+                .unwrap()
+        );
+        let precompiled = fs::read(precompiled_source(&locked_app)).unwrap();
+        assert_ne!(precompiled, planted);
+        let sealed = cache.get(&digest).unwrap();
+        assert_eq!(key.unseal(&digest, &sealed), Some(precompiled.as_slice()));
+    }
+
+    #[test]
+    fn unseals_only_untampered_artifacts() {
+        let key = ArtifactKey::generate().unwrap();
+        let mut sealed = key.seal("sha256:a", b"compiled");
+        assert_eq!(key.unseal("sha256:a", &sealed), Some(&b"compiled"[..]));
+        assert_eq!(key.unseal("sha256:b", &sealed), None);
+        assert_eq!(key.unseal("sha256:a", &sealed[..MAC_LEN - 1]), None);
+        *sealed.last_mut().unwrap() ^= 1;
+        assert_eq!(key.unseal("sha256:a", &sealed), None);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::info;
+use spin_expressions::{Key, Provider};
+
+use crate::{constants, container_env::ContainerEnv};
+
+/// Application variable provider reading one file per variable from a directory,
+/// the way Kubernetes projects Secrets and ConfigMaps into a volume.
+///
+/// The file of a variable is named after the variable, or after the variable in
+/// upper case. A single trailing newline is stripped from its content. Files are
+/// read again whenever their modification time or size changes, so rotated
+/// Secrets are picked up by the next invocation without restarting the pod.
+#[derive(Debug)]
+pub(crate) struct FileVariablesProvider {
+    dir: PathBuf,
+    cache: Mutex<HashMap<PathBuf, CachedValue>>,
+}
+
+#[derive(Debug)]
+struct CachedValue {
+    modified: SystemTime,
+    len: u64,
+    value: String,
+}
+
+impl FileVariablesProvider {
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates the provider for the directory configured by
+    /// [`constants::SPIN_VARIABLES_DIR_ENV`], if any.
+    pub(crate) fn from_env(env: &ContainerEnv) -> Result<Option<Self>> {
+        let Some(dir) = env.var(constants::SPIN_VARIABLES_DIR_ENV) else {
+            return Ok(None);
+        };
+        let dir = PathBuf::from(dir);
+        if !dir.is_dir() {
+            anyhow::bail!(
+                "{} is set to {}, which is not a directory",
+                constants::SPIN_VARIABLES_DIR_ENV,
+                dir.display()
+            );
+        }
+        info!(" >>> reading application variables from {}", dir.display());
+        Ok(Some(Self::new(dir)))
+    }
+
+    /// Returns whether a file provides the variable.
+    pub(crate) fn provides(&self, key: &str) -> bool {
+        matches!(self.read(key), Ok(Some(_)))
+    }
+
+    fn read(&self, key: &str) -> Result<Option<String>> {
+        for name in [key.to_string(), key.to_ascii_uppercase()] {
+            let path = self.dir.join(name);
+            // Metadata follows the symlinks Kubernetes swaps atomically on updates
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            return self.read_file(&path, &metadata).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn read_file(&self, path: &Path, metadata: &fs::Metadata) -> Result<String> {
+        let modified = metadata.modified()?;
+        let len = metadata.len();
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(cached) = cache.get(path) {
+            if cached.modified == modified && cached.len == len {
+                return Ok(cached.value.clone());
+            }
+            info!(
+                " >>> reloading application variable from {}",
+                path.display()
+            );
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read variable file {}", path.display()))?;
+        let value = content
+            .strip_suffix('\n')
+            .map(|value| value.strip_suffix('\r').unwrap_or(value))
+            .unwrap_or(&content)
+            .to_string();
+        cache.insert(
+            path.to_path_buf(),
+            CachedValue {
+                modified,
+                len,
+                value: value.clone(),
+            },
+        );
+        Ok(value)
+    }
+}
+
+#[async_trait]
+impl Provider for FileVariablesProvider {
+    async fn get(&self, key: &Key) -> Result<Option<String>> {
+        self.read(key.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_and_reloads_variable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = FileVariablesProvider::new(dir.path());
+        let key = Key::new("api_key").unwrap();
+
+        assert_eq!(provider.get(&key).await.unwrap(), None);
+
+        fs::write(dir.path().join("api_key"), "first\n").unwrap();
+        assert_eq!(provider.get(&key).await.unwrap().as_deref(), Some("first"));
+
+        fs::write(dir.path().join("api_key"), "rotated-secret").unwrap();
+        assert_eq!(
+            provider.get(&key).await.unwrap().as_deref(),
+            Some("rotated-secret")
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_upper_case_file_names() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("DB_PASSWORD"), "hunter2").unwrap();
+        let provider = FileVariablesProvider::new(dir.path());
+        let key = Key::new("db_password").unwrap();
+        assert_eq!(
+            provider.get(&key).await.unwrap().as_deref(),
+            Some("hunter2")
+        );
+    }
+
+    #[test]
+    fn disabled_unless_configured() {
+        let env = ContainerEnv::default();
+        assert!(FileVariablesProvider::from_env(&env).unwrap().is_none());
+        let env = ContainerEnv::from_iter([(constants::SPIN_VARIABLES_DIR_ENV, "/does/not/exist")]);
+        assert!(FileVariablesProvider::from_env(&env).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::Result;
+use http::{header, Response, StatusCode};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use serde::Serialize;
+
+use crate::server::{self, ServerHandle};
+
+/// Path of the liveness probe endpoint
+pub(crate) const LIVENESS_PATH: &str = "/healthz";
+/// Path of the readiness probe endpoint
+pub(crate) const READINESS_PATH: &str = "/readyz";
+
+/// Startup status of a single trigger of the application
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TriggerStatus {
+    /// The trigger has not been constructed yet
+    Pending,
+    /// The trigger was constructed and is running
+    Ready,
+    /// The trigger could not be constructed
+    Failed,
+    /// The trigger stopped running and is restarted by its restart policy
+    Restarting,
+    /// The trigger stopped running
+    Exited,
+}
+
+impl TriggerStatus {
+    /// Whether the trigger keeps the application from being ready. Triggers that
+    /// are restarting or whose exit is ignored do not, as the application keeps
+    /// running without them.
+    fn blocks_readiness(self) -> bool {
+        matches!(self, Self::Pending | Self::Failed)
+    }
+}
+
+/// Health of the Spin application as reported by the shim's probe endpoints.
+///
+/// The application is live once its `LockedApp` has been loaded and ready once
+/// all of its triggers have started and at least one of them is running.
+#[derive(Default)]
+pub(crate) struct HealthState {
+    live: AtomicBool,
+    triggers: Mutex<BTreeMap<String, TriggerStatus>>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    live: bool,
+    ready: bool,
+    triggers: BTreeMap<String, TriggerStatus>,
+}
+
+impl HealthState {
+    pub(crate) fn set_live(&self) {
+        self.live.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn set_trigger_status(&self, trigger_type: &str, status: TriggerStatus) {
+        self.triggers
+            .lock()
+            .unwrap()
+            .insert(trigger_type.to_string(), status);
+    }
+
+    fn report(&self) -> Report {
+        let triggers = self.triggers.lock().unwrap().clone();
+        let live = self.live.load(Ordering::SeqCst);
+        let ready = live
+            && triggers.values().any(|s| *s == TriggerStatus::Ready)
+            && !triggers.values().any(|s| s.blocks_readiness());
+        Report {
+            live,
+            ready,
+            triggers,
+        }
+    }
+
+    fn respond(&self, path: &str) -> Response<Full<Bytes>> {
+        let report = self.report();
+        let healthy = match path {
+            LIVENESS_PATH => report.live,
+            READINESS_PATH => report.ready,
+            _ => {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Full::default())
+                    .unwrap()
+            }
+        };
+        let status = if healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        let body = serde_json::to_vec(&report).unwrap_or_default();
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap()
+    }
+}
+
+/// Starts serving the liveness and readiness endpoints on the given address.
+pub(crate) async fn serve(addr: SocketAddr, state: Arc<HealthState>) -> Result<ServerHandle> {
+    server::serve(addr, "health checks", move |path| state.respond(path)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_live_until_app_loaded() {
+        let state = HealthState::default();
+        assert_eq!(
+            state.respond(LIVENESS_PATH).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        state.set_live();
+        assert_eq!(state.respond(LIVENESS_PATH).status(), StatusCode::OK);
+        assert_eq!(state.respond("/other").status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn ready_once_all_triggers_ready() {
+        let state = HealthState::default();
+        state.set_live();
+        assert!(!state.report().ready);
+
+        state.set_trigger_status("http", TriggerStatus::Pending);
+        state.set_trigger_status("redis", TriggerStatus::Pending);
+        state.set_trigger_status("http", TriggerStatus::Ready);
+        let report = state.report();
+        assert!(!report.ready);
+        assert_eq!(report.triggers["http"], TriggerStatus::Ready);
+        assert_eq!(report.triggers["redis"], TriggerStatus::Pending);
+        assert_eq!(
+            state.respond(READINESS_PATH).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        state.set_trigger_status("redis", TriggerStatus::Ready);
+        assert_eq!(state.respond(READINESS_PATH).status(), StatusCode::OK);
+
+        state.set_trigger_status("redis", TriggerStatus::Failed);
+        assert!(!state.report().ready);
+    }
+
+    #[test]
+    fn ready_while_triggers_restart_or_exit_ignored() {
+        let state = HealthState::default();
+        state.set_live();
+        state.set_trigger_status("http", TriggerStatus::Ready);
+        state.set_trigger_status("redis", TriggerStatus::Restarting);
+        state.set_trigger_status("mqtt", TriggerStatus::Exited);
+        assert!(state.report().ready);
+
+        state.set_trigger_status("http", TriggerStatus::Restarting);
+        assert!(!state.report().ready);
+    }
+}
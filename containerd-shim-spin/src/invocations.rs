@@ -0,0 +1,317 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use spin_app::App;
+use spin_factor_wasi::WasiFactor;
+use spin_factors_executor::{ConfiguredApp, ExecutorHooks, FactorsInstanceBuilder};
+use spin_runtime_factors::TriggerFactors;
+use tokio::sync::watch;
+use wasmtime_wasi::{pipe::ClosedInputStream, HostInputStream, StdinStream};
+
+use crate::metrics::Metrics;
+
+/// Tracks the component invocations in flight, so that they can be drained when
+/// the container is stopped.
+///
+/// An invocation starts when a trigger prepares an instance of a component and
+/// ends when the instance is dropped, which is when its duration is recorded in
+/// the metrics, if collected. Once draining, no new invocations are started.
+///
+/// The end of an invocation is only observed through the standard input of its
+/// instance (see [`InvocationStdin`]), so invocations of components whose trigger
+/// sets their standard input itself are not tracked. Once any such invocation
+/// started, draining waits for the whole drain period.
+pub(crate) struct Invocations {
+    in_flight: watch::Sender<usize>,
+    draining: AtomicBool,
+    untracked: AtomicBool,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl Invocations {
+    pub(crate) fn new(metrics: Option<Arc<Metrics>>) -> Self {
+        Self {
+            in_flight: watch::Sender::new(0),
+            draining: AtomicBool::new(false),
+            untracked: AtomicBool::new(false),
+            metrics,
+        }
+    }
+
+    /// Starts an invocation of the given component, which ends when the returned
+    /// [`Invocation`] is dropped.
+    pub(crate) fn start(self: &Arc<Self>, component_id: &str) -> Result<Invocation> {
+        self.ensure_not_draining()?;
+        self.in_flight.send_modify(|n| *n += 1);
+        Ok(Invocation {
+            invocations: self.clone(),
+            component_id: component_id.to_string(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Starts an invocation whose end cannot be observed.
+    pub(crate) fn start_untracked(&self) -> Result<()> {
+        self.ensure_not_draining()?;
+        self.untracked.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn ensure_not_draining(&self) -> Result<()> {
+        if self.is_draining() {
+            anyhow::bail!("not accepting new invocations: the application is shutting down");
+        }
+        Ok(())
+    }
+
+    /// Returns whether new invocations are no longer started.
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of invocations in flight.
+    pub(crate) fn in_flight(&self) -> usize {
+        *self.in_flight.borrow()
+    }
+
+    /// Returns whether any invocation whose end cannot be observed was started.
+    pub(crate) fn has_untracked(&self) -> bool {
+        self.untracked.load(Ordering::SeqCst)
+    }
+
+    /// Stops starting new invocations and waits up to `drain_period` for those in
+    /// flight to end, or for the whole period if any untracked invocation was
+    /// started. Returns the number of tracked invocations still in flight.
+    pub(crate) async fn drain(&self, drain_period: Duration) -> usize {
+        self.draining.store(true, Ordering::SeqCst);
+        let deadline = tokio::time::Instant::now() + drain_period;
+        let mut in_flight = self.in_flight.subscribe();
+        // The sender lives as long as `self`, so waiting can only time out
+        let _ = tokio::time::timeout_at(deadline, in_flight.wait_for(|n| *n == 0)).await;
+        if self.has_untracked() {
+            tokio::time::sleep_until(deadline).await;
+        }
+        self.in_flight()
+    }
+}
+
+/// A component invocation in flight, ending when dropped.
+pub(crate) struct Invocation {
+    invocations: Arc<Invocations>,
+    component_id: String,
+    started: Instant,
+}
+
+impl Drop for Invocation {
+    fn drop(&mut self) {
+        self.invocations.in_flight.send_modify(|n| *n -= 1);
+        if let Some(metrics) = &self.invocations.metrics {
+            metrics.record_invocation_duration(&self.component_id, self.started.elapsed());
+        }
+    }
+}
+
+/// Standard input of an instance holding its [`Invocation`].
+///
+/// Spin does not notify executor hooks when an instance is dropped, but drops the
+/// WASI context of the instance, and with it this stream, together with its store.
+/// Like the default standard input of Spin components, the stream is closed, so it
+/// only replaces standard input that no trigger would otherwise set.
+struct InvocationStdin {
+    _invocation: Invocation,
+}
+
+impl StdinStream for InvocationStdin {
+    fn stream(&self) -> Box<dyn HostInputStream> {
+        Box::new(ClosedInputStream)
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+/// Executor hook starting an invocation for each new instance.
+///
+/// Spin's WAGI executor pipes the request body to the standard input of the
+/// instance after the hooks prepared it, so the invocations of WAGI components
+/// are started untracked and their standard input is left to the trigger.
+pub(crate) struct InvocationsHook {
+    invocations: Arc<Invocations>,
+    wagi_components: HashSet<String>,
+}
+
+impl InvocationsHook {
+    pub(crate) fn new(invocations: Arc<Invocations>) -> Self {
+        Self {
+            invocations,
+            wagi_components: HashSet::new(),
+        }
+    }
+}
+
+impl<U: Send + 'static> ExecutorHooks<TriggerFactors, U> for InvocationsHook {
+    fn configure_app(&mut self, configured_app: &ConfiguredApp<TriggerFactors>) -> Result<()> {
+        self.wagi_components = wagi_components(configured_app.app())?;
+        Ok(())
+    }
+
+    fn prepare_instance(
+        &self,
+        builder: &mut FactorsInstanceBuilder<TriggerFactors, U>,
+    ) -> anyhow::Result<()> {
+        let component_id = builder.app_component().id();
+        if self.wagi_components.contains(component_id) {
+            return self.invocations.start_untracked();
+        }
+        let invocation = self.invocations.start(component_id)?;
+        builder
+            .factor_builder::<WasiFactor>()
+            .context("missing WASI factor")?
+            .stdin(InvocationStdin {
+                _invocation: invocation,
+            });
+        Ok(())
+    }
+}
+
+/// The parts of an HTTP trigger config that select its executor.
+#[derive(Deserialize)]
+struct HttpExecutorConfig {
+    #[serde(default)]
+    component: Option<String>,
+    #[serde(default)]
+    executor: Option<serde_json::Value>,
+}
+
+/// Returns the components of the application run by the WAGI executor of an
+/// HTTP trigger.
+fn wagi_components(app: &App) -> Result<HashSet<String>> {
+    Ok(app
+        .trigger_configs::<HttpExecutorConfig>("http")?
+        .into_iter()
+        .filter(|(_, config)| {
+            config
+                .executor
+                .as_ref()
+                .and_then(|executor| executor.get("type")?.as_str())
+                == Some("wagi")
+        })
+        .filter_map(|(_, config)| config.component)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use spin_app::locked::LockedApp;
+
+    use super::*;
+
+    fn invocations() -> Arc<Invocations> {
+        Arc::new(Invocations::new(None))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_waits_for_invocations_in_flight() {
+        let invocations = invocations();
+        let invocation = invocations.start("hello").unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            drop(invocation);
+        });
+
+        let started = tokio::time::Instant::now();
+        assert_eq!(invocations.drain(Duration::from_secs(30)).await, 0);
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+        assert!(invocations.start("hello").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_cuts_off_invocations_after_drain_period() {
+        let invocations = invocations();
+        drop(invocations.start("hello").unwrap());
+        let _stuck = invocations.start("hello").unwrap();
+        assert_eq!(invocations.in_flight(), 1);
+
+        let started = tokio::time::Instant::now();
+        assert_eq!(invocations.drain(Duration::from_secs(5)).await, 1);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn drain_without_invocations_returns_immediately() {
+        assert_eq!(invocations().drain(Duration::ZERO).await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_waits_for_drain_period_after_untracked_invocations() {
+        let invocations = invocations();
+        drop(invocations.start("hello").unwrap());
+        invocations.start_untracked().unwrap();
+
+        let started = tokio::time::Instant::now();
+        assert_eq!(invocations.drain(Duration::from_secs(5)).await, 0);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        assert!(invocations.start_untracked().is_err());
+    }
+
+    #[test]
+    fn records_invocation_duration_in_metrics_of_the_app() {
+        let metrics = Arc::new(Metrics::default());
+        let app = Arc::new(Invocations::new(Some(metrics.clone())));
+        drop(app.start("hello").unwrap());
+        // Invocations of another application are not recorded
+        drop(invocations().start("other").unwrap());
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("component=\"hello\""));
+        assert!(!rendered.contains("component=\"other\""));
+    }
+
+    #[test]
+    fn finds_wagi_components() {
+        let app_json = r#"{
+            "spin_lock_version": 1,
+            "triggers": [
+                {
+                    "id": "t1",
+                    "trigger_type": "http",
+                    "trigger_config": { "component": "spin", "route": "/spin" }
+                },
+                {
+                    "id": "t2",
+                    "trigger_type": "http",
+                    "trigger_config": {
+                        "component": "wagi",
+                        "route": "/wagi",
+                        "executor": { "type": "wagi", "entrypoint": "_start" }
+                    }
+                },
+                {
+                    "id": "t3",
+                    "trigger_type": "http",
+                    "trigger_config": {
+                        "component": "explicit",
+                        "route": "/explicit",
+                        "executor": { "type": "spin" }
+                    }
+                }
+            ],
+            "components": []
+        }"#;
+        let locked_app = LockedApp::from_json(app_json.as_bytes()).unwrap();
+        let app = App::new("test", locked_app);
+        assert_eq!(
+            wagi_components(&app).unwrap(),
+            HashSet::from(["wagi".to_string()])
+        );
+    }
+}
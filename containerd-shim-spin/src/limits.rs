@@ -0,0 +1,211 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use log::info;
+use spin_app::locked::LockedApp;
+use spin_factors_executor::{ExecutorHooks, FactorsInstanceBuilder};
+use spin_runtime_factors::TriggerFactors;
+
+use crate::{constants, container_env::ContainerEnv};
+
+/// Resource limits applied to every instance of the application components.
+///
+/// Limits are read from the container environment: a default for all components
+/// and optional overrides per component id.
+///
+/// Linear memory is limited for the instances of every trigger. Spin creates the
+/// store of an instance after the executor hooks of the shim have run, installs its
+/// own resource limiter and sets an effectively infinite epoch deadline, so the
+/// execution time of an invocation can only be limited by the triggers of the shim
+/// itself, i.e. the cron trigger, which fails runs exceeding it with a distinct
+/// error. Fuel and per-instance table limits cannot be applied by the shim; table
+/// sizes are only bounded engine-wide by the pooling allocator (see
+/// [`crate::pooling`]). A guest exceeding its memory limit sees memory growth fail
+/// rather than a distinct error, and the resulting trap is reported by the trigger
+/// like any other, e.g. as a `500` response by the HTTP trigger.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct InstanceLimits {
+    default_max_memory: Option<usize>,
+    component_max_memory: HashMap<String, usize>,
+    default_max_invocation_time: Option<Duration>,
+    component_max_invocation_time: HashMap<String, Duration>,
+}
+
+impl InstanceLimits {
+    pub(crate) fn from_env(env: &ContainerEnv, locked_app: &LockedApp) -> Result<Self> {
+        let default_max_memory = parse_memory_env(env, constants::SPIN_MAX_INSTANCE_MEMORY_ENV)?;
+        let default_max_invocation_time =
+            parse_seconds_env(env, constants::SPIN_MAX_INVOCATION_SECONDS_ENV)?;
+        let mut component_max_memory = HashMap::new();
+        let mut component_max_invocation_time = HashMap::new();
+        for component in &locked_app.components {
+            let name = component_env(constants::SPIN_MAX_INSTANCE_MEMORY_ENV, &component.id);
+            if let Some(max) = parse_memory_env(env, &name)? {
+                component_max_memory.insert(component.id.clone(), max);
+            }
+            let name = component_env(constants::SPIN_MAX_INVOCATION_SECONDS_ENV, &component.id);
+            if let Some(max) = parse_seconds_env(env, &name)? {
+                component_max_invocation_time.insert(component.id.clone(), max);
+            }
+        }
+        Ok(Self {
+            default_max_memory,
+            component_max_memory,
+            default_max_invocation_time,
+            component_max_invocation_time,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.default_max_memory.is_none()
+            && self.component_max_memory.is_empty()
+            && self.default_max_invocation_time.is_none()
+            && self.component_max_invocation_time.is_empty()
+    }
+
+    /// Returns the time an invocation of the given component may run for.
+    pub(crate) fn max_invocation_time(&self, component_id: &str) -> Option<Duration> {
+        self.component_max_invocation_time
+            .get(component_id)
+            .copied()
+            .or(self.default_max_invocation_time)
+    }
+
+    fn max_memory(&self, component_id: &str) -> Option<usize> {
+        self.component_max_memory
+            .get(component_id)
+            .copied()
+            .or(self.default_max_memory)
+    }
+
+    /// Logs the limits applied to the instances of the application.
+    pub(crate) fn log(&self) {
+        if let Some(max) = self.default_max_memory {
+            info!(" >>> limiting component instances to {max} bytes of memory");
+        }
+        for (component, max) in &self.component_max_memory {
+            info!(component = component.as_str(); " >>> limiting instances of component {component} to {max} bytes of memory");
+        }
+        if let Some(max) = self.default_max_invocation_time {
+            info!(
+                " >>> limiting invocations of the cron trigger to {}s",
+                max.as_secs()
+            );
+        }
+        for (component, max) in &self.component_max_invocation_time {
+            info!(component = component.as_str(); " >>> limiting cron invocations of component {component} to {}s", max.as_secs());
+        }
+    }
+}
+
+/// Name of the environment variable overriding a limit for a component, e.g.
+/// `SPIN_MAX_INSTANCE_MEMORY_MY_COMPONENT` for `my-component`.
+fn component_env(name: &str, component_id: &str) -> String {
+    format!(
+        "{name}_{}",
+        component_id.to_ascii_uppercase().replace('-', "_")
+    )
+}
+
+fn parse_memory_env(env: &ContainerEnv, name: &str) -> Result<Option<usize>> {
+    match env.var(name) {
+        Some(bytes) if !bytes.is_empty() => {
+            bytes.trim().parse::<usize>().map(Some).with_context(|| {
+                format!("invalid value {bytes:?} for {name}: expected a size in bytes")
+            })
+        }
+        _ => Ok(None),
+    }
+}
+
+fn parse_seconds_env(env: &ContainerEnv, name: &str) -> Result<Option<Duration>> {
+    match env.var(name) {
+        Some(secs) if !secs.is_empty() => secs
+            .trim()
+            .parse::<u64>()
+            .map(|secs| Some(Duration::from_secs(secs)))
+            .with_context(|| {
+                format!("invalid value {secs:?} for {name}: expected a number of seconds")
+            }),
+        _ => Ok(None),
+    }
+}
+
+/// Executor hook applying the [`InstanceLimits`] to each new instance.
+pub(crate) struct LimitsHook(pub(crate) Arc<InstanceLimits>);
+
+impl<U: Send + 'static> ExecutorHooks<TriggerFactors, U> for LimitsHook {
+    fn prepare_instance(
+        &self,
+        builder: &mut FactorsInstanceBuilder<TriggerFactors, U>,
+    ) -> anyhow::Result<()> {
+        if let Some(max) = self.0.max_memory(builder.app_component().id()) {
+            builder.store_builder().max_memory_size(max);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locked_app() -> LockedApp {
+        let app_json = r#"
+        {
+            "spin_lock_version": 1,
+            "components": [
+                { "id": "small-one", "source": { "content_type": "application/wasm", "source": "file:///small.wasm" } },
+                { "id": "other", "source": { "content_type": "application/wasm", "source": "file:///other.wasm" } }
+            ],
+            "triggers": []
+        }"#;
+        LockedApp::from_json(app_json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn no_limits_by_default() {
+        let env = ContainerEnv::default();
+        assert!(InstanceLimits::from_env(&env, &locked_app())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn component_overrides_default() {
+        let env = ContainerEnv::from_iter([
+            (constants::SPIN_MAX_INSTANCE_MEMORY_ENV, "1048576"),
+            ("SPIN_MAX_INSTANCE_MEMORY_SMALL_ONE", "65536"),
+        ]);
+        let limits = InstanceLimits::from_env(&env, &locked_app()).unwrap();
+        assert_eq!(limits.max_memory("small-one"), Some(65536));
+        assert_eq!(limits.max_memory("other"), Some(1048576));
+    }
+
+    #[test]
+    fn component_overrides_default_invocation_time() {
+        let env = ContainerEnv::from_iter([
+            (constants::SPIN_MAX_INVOCATION_SECONDS_ENV, "30"),
+            ("SPIN_MAX_INVOCATION_SECONDS_SMALL_ONE", "5"),
+        ]);
+        let limits = InstanceLimits::from_env(&env, &locked_app()).unwrap();
+        assert!(!limits.is_empty());
+        assert_eq!(
+            limits.max_invocation_time("small-one"),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            limits.max_invocation_time("other"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(limits.max_memory("other"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_limit() {
+        let env = ContainerEnv::from_iter([(constants::SPIN_MAX_INSTANCE_MEMORY_ENV, "64Mi")]);
+        assert!(InstanceLimits::from_env(&env, &locked_app()).is_err());
+        let env = ContainerEnv::from_iter([(constants::SPIN_MAX_INVOCATION_SECONDS_ENV, "1m")]);
+        assert!(InstanceLimits::from_env(&env, &locked_app()).is_err());
+    }
+}
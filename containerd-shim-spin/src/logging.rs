@@ -0,0 +1,270 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{self, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::Path,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{SecondsFormat, Utc};
+use containerd_shim_wasm::Config;
+use log::{kv, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value};
+
+use crate::constants;
+
+/// Log level of the shim unless configured otherwise, kept low for performance
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Error;
+
+/// Id of the application the container runs, set once the container
+/// environment is known
+static APP_ID: OnceLock<String> = OnceLock::new();
+
+/// Format of the log lines written by the shim
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum LogFormat {
+    /// The freeform text logging of containerd shims
+    #[default]
+    Text,
+    /// One JSON object per line carrying correlation fields
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown log format {other:?}")),
+        }
+    }
+}
+
+/// Configures logging of the shim from [`constants::SPIN_SHIM_LOG_FORMAT_ENV`] and
+/// [`constants::SPIN_SHIM_LOG_LEVEL_ENV`], returning the shim configuration.
+///
+/// In JSON mode the logger is installed here, as the one set up by the shim
+/// library only writes text.
+pub(crate) fn init() -> Config {
+    let level = env::var(constants::SPIN_SHIM_LOG_LEVEL_ENV)
+        .ok()
+        .and_then(|level| LevelFilter::from_str(level.trim()).ok())
+        .unwrap_or(DEFAULT_LOG_LEVEL);
+    let format = env::var(constants::SPIN_SHIM_LOG_FORMAT_ENV)
+        .ok()
+        .and_then(|format| format.parse().ok())
+        .unwrap_or_default();
+    let mut config = Config {
+        default_log_level: level.to_string().to_lowercase(),
+        ..Default::default()
+    };
+    if format == LogFormat::Json {
+        let logger = JsonLogger {
+            level,
+            shim_id: shim_id(env::args()),
+            output: Mutex::new(log_output(Path::new("log"))),
+        };
+        if log::set_boxed_logger(Box::new(logger)).is_ok() {
+            log::set_max_level(level);
+            config.no_setup_logger = true;
+        }
+    }
+    config
+}
+
+/// Records the id of the application, taken from the `HOSTNAME` of the container.
+///
+/// The logger is shared by the whole process, so it fails if the process already
+/// runs an application with another id rather than mislabel its logs.
+pub(crate) fn set_app_id(app_id: &str) -> anyhow::Result<()> {
+    let current = APP_ID.get_or_init(|| app_id.to_string());
+    if current != app_id {
+        anyhow::bail!("cannot log for application {app_id}: the process already logs for application {current}");
+    }
+    Ok(())
+}
+
+/// The id containerd started the shim with, passed as `-id <id>`.
+///
+/// The shim serves all containers of a pod, so this is the id of the pod sandbox
+/// rather than of a container, and is logged as `shim_id`.
+fn shim_id(mut args: impl Iterator<Item = String>) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == "-id" || arg == "--id" {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// Writes to the log fifo containerd reads shim logs from, falling back to stderr.
+///
+/// The fifo is opened without blocking, as containerd only reads it while the
+/// shim server runs: in other shim actions, such as `delete`, opening it for
+/// writing would wait for a reader forever. Writes block once the fifo is open,
+/// so that no lines are dropped while containerd catches up.
+fn log_output(path: &Path) -> Box<dyn Write + Send> {
+    let fifo = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path);
+    match fifo {
+        // SAFETY: the descriptor is owned by the open fifo
+        Ok(fifo) if unsafe { libc::fcntl(fifo.as_raw_fd(), libc::F_SETFL, 0) } == 0 => {
+            Box::new(fifo)
+        }
+        _ => Box::new(io::stderr()),
+    }
+}
+
+/// Logger writing one JSON object per line with the level, target and message
+/// of each record, the ids of the shim and application, and the key-values
+/// of the record such as `trigger` and `component`.
+struct JsonLogger {
+    level: LevelFilter,
+    shim_id: Option<String>,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogger {
+    fn format(&self, record: &Record) -> String {
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".into(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+                .into(),
+        );
+        line.insert("level".into(), record.level().as_str().into());
+        line.insert("target".into(), record.target().into());
+        line.insert(
+            "message".into(),
+            record.args().to_string().trim_start_matches(" >>> ").into(),
+        );
+        if let Some(shim_id) = &self.shim_id {
+            line.insert("shim_id".into(), shim_id.as_str().into());
+        }
+        if let Some(app_id) = APP_ID.get() {
+            line.insert("app_id".into(), app_id.as_str().into());
+        }
+        let _ = record.key_values().visit(&mut Fields(&mut line));
+        Value::Object(line).to_string()
+    }
+}
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> kv::VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.to_string(), value.to_string().into());
+        Ok(())
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record);
+        if let Ok(mut output) = self.output.lock() {
+            let _ = writeln!(output, "{line}");
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut output) = self.output.lock() {
+            let _ = output.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use log::Level;
+
+    use super::*;
+
+    #[test]
+    fn parses_shim_id_from_args() {
+        let args = ["shim", "-namespace", "k8s.io", "-id", "abc123", "start"];
+        assert_eq!(
+            shim_id(args.iter().map(|s| s.to_string())),
+            Some("abc123".to_string())
+        );
+        assert_eq!(shim_id(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn rejects_another_app_id() {
+        set_app_id("app-a").unwrap();
+        set_app_id("app-a").unwrap();
+        assert!(set_app_id("app-b").is_err());
+        assert_eq!(APP_ID.get().unwrap(), "app-a");
+    }
+
+    #[test]
+    fn formats_records_as_json() {
+        let logger = JsonLogger {
+            level: LevelFilter::Info,
+            shim_id: Some("abc123".to_string()),
+            output: Mutex::new(Box::new(io::sink())),
+        };
+        let line = logger.format(
+            &Record::builder()
+                .args(format_args!(" >>> running http trigger"))
+                .level(Level::Info)
+                .target("containerd_shim_spin_v2::trigger")
+                .key_values(&("trigger", "http"))
+                .build(),
+        );
+        let line: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "running http trigger");
+        assert_eq!(line["shim_id"], "abc123");
+        assert_eq!(line["trigger"], "http");
+        assert!(line["timestamp"].is_string());
+    }
+
+    #[test]
+    fn opens_log_fifo_only_with_a_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        // SAFETY: the path is a valid C string
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        // Without a reader the fifo is skipped instead of blocking the shim
+        let mut output = log_output(&path);
+        writeln!(output, "to stderr").unwrap();
+
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+        let mut output = log_output(&path);
+        writeln!(output, "to fifo").unwrap();
+        drop(output);
+        let mut line = String::new();
+        reader.read_to_string(&mut line).unwrap();
+        assert_eq!(line, "to fifo\n");
+    }
+
+    #[test]
+    fn parses_log_format() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
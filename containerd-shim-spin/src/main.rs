@@ -1,21 +1,32 @@
 use containerd_shim_wasm::{
     container::Instance,
     sandbox::cli::{revision, shim_main, version},
-    Config,
 };
 
+mod compile_cache;
+mod component_selection;
 mod constants;
+mod container_env;
+mod cron_trigger;
 mod engine;
+mod file_precompile;
+mod file_variables;
+mod health;
+mod invocations;
+mod limits;
+mod logging;
+mod metrics;
+mod pooling;
+mod runtime_config;
+mod server;
+mod signature;
 mod source;
 mod trigger;
 mod utils;
 
 fn main() {
-    // Configure the shim to have only error level logging for performance improvements.
-    let shim_config = Config {
-        default_log_level: "error".to_string(),
-        ..Default::default()
-    };
+    // The shim only logs errors unless configured otherwise for performance improvements.
+    let shim_config = logging::init();
     shim_main::<Instance<engine::SpinEngine>>(
         "spin",
         version!(),
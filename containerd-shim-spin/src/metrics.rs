@@ -0,0 +1,385 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use http::{header, Response, StatusCode};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use spin_app::locked::LockedApp;
+use spin_factors_executor::{ExecutorHooks, FactorsInstanceBuilder};
+use spin_runtime_factors::TriggerFactors;
+
+use crate::server::{self, ServerHandle};
+
+/// Path of the metrics endpoint
+pub(crate) const METRICS_PATH: &str = "/metrics";
+
+struct Descriptor {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+}
+
+const COMPONENT_INVOCATIONS: Descriptor = Descriptor {
+    name: "spin_component_invocations_total",
+    help: "Number of component instances prepared to handle an invocation",
+    kind: "counter",
+};
+const COMPONENT_INVOCATION_SECONDS: Descriptor = Descriptor {
+    name: "spin_component_invocation_duration_seconds",
+    help:
+        "Time from preparing a component instance until it was dropped at the end of its invocation",
+    kind: "histogram",
+};
+const COMPONENT_INVOCATION_ERRORS: Descriptor = Descriptor {
+    name: "spin_component_invocation_errors_total",
+    help: "Number of component invocations that failed, for the cron and command triggers",
+    kind: "counter",
+};
+const COMPONENT_INSTANTIATION_SECONDS: Descriptor = Descriptor {
+    name: "spin_component_instantiation_duration_seconds",
+    help: "Time taken to instantiate a component for an invocation, for the cron trigger",
+    kind: "histogram",
+};
+const TRIGGER_STARTUP_SECONDS: Descriptor = Descriptor {
+    name: "spin_trigger_startup_seconds",
+    help: "Time taken to load the components of a trigger and start it",
+    kind: "gauge",
+};
+const TRIGGER_EXITS: Descriptor = Descriptor {
+    name: "spin_trigger_exits_total",
+    help: "Number of times a trigger stopped running",
+    kind: "counter",
+};
+
+const TRIGGER_RESTARTS: Descriptor = Descriptor {
+    name: "spin_trigger_restarts_total",
+    help: "Number of times a trigger was restarted by its restart policy",
+    kind: "counter",
+};
+
+/// Upper bounds of the buckets of the duration histograms, in seconds
+const SECONDS_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+/// Registry of the metrics collected by the shim, served in the Prometheus text format.
+///
+/// Component invocations are counted when Spin prepares a component instance,
+/// as every invocation runs in a fresh instance, and timed until the instance is
+/// dropped (see [`crate::invocations`]). Trigger metrics are recorded by the shim
+/// as it starts and supervises the application triggers.
+///
+/// Spin handles the result of an invocation and instantiates the component after
+/// the executor hooks of the shim have run (see [`crate::limits`]), so invocation
+/// errors and instantiation time are only recorded for the triggers that expose
+/// them: the cron trigger of the shim, and the command trigger, which fails with
+/// the single invocation of its component. Memory high-water marks are not
+/// recorded for any trigger.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+    component_triggers: Mutex<HashMap<String, String>>,
+}
+
+struct Family {
+    help: &'static str,
+    kind: &'static str,
+    samples: BTreeMap<Labels, f64>,
+    histograms: BTreeMap<Labels, Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Number of observations per bucket of [`SECONDS_BUCKETS`]
+    buckets: [u64; SECONDS_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    /// Records which trigger type invokes each component of the application.
+    pub(crate) fn register_app(&self, locked_app: &LockedApp) {
+        let mut component_triggers = self.component_triggers.lock().unwrap();
+        for trigger in &locked_app.triggers {
+            if let Some(component) = trigger
+                .trigger_config
+                .get("component")
+                .and_then(|c| c.as_str())
+            {
+                component_triggers
+                    .entry(component.to_string())
+                    .or_insert_with(|| trigger.trigger_type.clone());
+            }
+        }
+    }
+
+    pub(crate) fn record_invocation(&self, component_id: &str) {
+        self.add(
+            &COMPONENT_INVOCATIONS,
+            self.component_labels(component_id),
+            1.0,
+        );
+    }
+
+    pub(crate) fn record_invocation_duration(&self, component_id: &str, duration: Duration) {
+        self.observe(
+            &COMPONENT_INVOCATION_SECONDS,
+            self.component_labels(component_id),
+            duration,
+        );
+    }
+
+    pub(crate) fn record_invocation_error(&self, component_id: &str) {
+        self.add(
+            &COMPONENT_INVOCATION_ERRORS,
+            self.component_labels(component_id),
+            1.0,
+        );
+    }
+
+    pub(crate) fn record_instantiation_duration(&self, component_id: &str, duration: Duration) {
+        self.observe(
+            &COMPONENT_INSTANTIATION_SECONDS,
+            self.component_labels(component_id),
+            duration,
+        );
+    }
+
+    fn observe(&self, descriptor: &Descriptor, labels: Labels, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut families = self.families.lock().unwrap();
+        let histogram = Self::family(&mut families, descriptor)
+            .histograms
+            .entry(labels)
+            .or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(SECONDS_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    fn component_labels(&self, component_id: &str) -> Labels {
+        let trigger_type = self
+            .component_triggers
+            .lock()
+            .unwrap()
+            .get(component_id)
+            .cloned()
+            .unwrap_or_default();
+        vec![
+            ("trigger", trigger_type),
+            ("component", component_id.to_string()),
+        ]
+    }
+
+    pub(crate) fn record_trigger_started(&self, trigger_type: &str, startup: Duration) {
+        self.set(
+            &TRIGGER_STARTUP_SECONDS,
+            vec![("trigger", trigger_type.to_string())],
+            startup.as_secs_f64(),
+        );
+    }
+
+    pub(crate) fn record_trigger_exit(&self, trigger_type: &str, result: &Result<()>) {
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+        self.add(
+            &TRIGGER_EXITS,
+            vec![
+                ("trigger", trigger_type.to_string()),
+                ("outcome", outcome.to_string()),
+            ],
+            1.0,
+        );
+    }
+
+    pub(crate) fn record_trigger_restart(&self, trigger_type: &str) {
+        self.add(
+            &TRIGGER_RESTARTS,
+            vec![("trigger", trigger_type.to_string())],
+            1.0,
+        );
+    }
+
+    fn add(&self, descriptor: &Descriptor, labels: Labels, value: f64) {
+        self.update(descriptor, labels, |sample| *sample += value);
+    }
+
+    fn set(&self, descriptor: &Descriptor, labels: Labels, value: f64) {
+        self.update(descriptor, labels, |sample| *sample = value);
+    }
+
+    fn update(&self, descriptor: &Descriptor, labels: Labels, f: impl FnOnce(&mut f64)) {
+        let mut families = self.families.lock().unwrap();
+        let family = Self::family(&mut families, descriptor);
+        f(family.samples.entry(labels).or_insert(0.0));
+    }
+
+    fn family<'a>(
+        families: &'a mut BTreeMap<&'static str, Family>,
+        descriptor: &Descriptor,
+    ) -> &'a mut Family {
+        families.entry(descriptor.name).or_insert_with(|| Family {
+            help: descriptor.help,
+            kind: descriptor.kind,
+            samples: BTreeMap::new(),
+            histograms: BTreeMap::new(),
+        })
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let _ = writeln!(out, "# HELP {name} {}", family.help);
+            let _ = writeln!(out, "# TYPE {name} {}", family.kind);
+            for (labels, value) in &family.samples {
+                let labels = render_labels(labels);
+                let _ = writeln!(out, "{name}{{{labels}}} {value}");
+            }
+            for (labels, histogram) in &family.histograms {
+                let labels = render_labels(labels);
+                for (bucket, bound) in histogram.buckets.iter().zip(SECONDS_BUCKETS) {
+                    let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {bucket}");
+                }
+                let count = histogram.count;
+                let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+                let _ = writeln!(out, "{name}_sum{{{labels}}} {}", histogram.sum);
+                let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+            }
+        }
+        out
+    }
+
+    fn respond(&self, path: &str) -> Response<Full<Bytes>> {
+        if path != METRICS_PATH {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::default())
+                .unwrap();
+        }
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Full::new(Bytes::from(self.render())))
+            .unwrap()
+    }
+}
+
+fn render_labels(labels: &[(&'static str, String)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{key}=\"{}\"", escape_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Starts serving the metrics endpoint on the given address.
+pub(crate) async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<ServerHandle> {
+    server::serve(addr, "metrics", move |path| metrics.respond(path)).await
+}
+
+/// Executor hook counting the component instances prepared by a trigger.
+pub(crate) struct MetricsHook(pub(crate) Arc<Metrics>);
+
+impl<U: Send + 'static> ExecutorHooks<TriggerFactors, U> for MetricsHook {
+    fn prepare_instance(
+        &self,
+        builder: &mut FactorsInstanceBuilder<TriggerFactors, U>,
+    ) -> anyhow::Result<()> {
+        self.0.record_invocation(builder.app_component().id());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text_format() {
+        let app_json = r#"
+        {
+            "spin_lock_version": 1,
+            "components": [],
+            "triggers": [
+                { "id": "t1", "trigger_type": "http", "trigger_config": { "component": "hello", "route": "/..." } }
+            ]
+        }"#;
+        let locked_app = LockedApp::from_json(app_json.as_bytes()).unwrap();
+        let metrics = Metrics::default();
+        metrics.register_app(&locked_app);
+        metrics.record_invocation("hello");
+        metrics.record_invocation("hello");
+        metrics.record_trigger_started("http", Duration::from_millis(1500));
+        metrics.record_trigger_exit("http", &Err(anyhow::anyhow!("boom")));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE spin_component_invocations_total counter\n"));
+        assert!(rendered.contains(
+            "spin_component_invocations_total{trigger=\"http\",component=\"hello\"} 2\n"
+        ));
+        assert!(rendered.contains("spin_trigger_startup_seconds{trigger=\"http\"} 1.5\n"));
+        assert!(
+            rendered.contains("spin_trigger_exits_total{trigger=\"http\",outcome=\"error\"} 1\n")
+        );
+    }
+
+    #[test]
+    fn renders_invocation_errors_and_instantiation_duration() {
+        let metrics = Metrics::default();
+        metrics.record_invocation_error("hello");
+        metrics.record_instantiation_duration("hello", Duration::from_millis(20));
+
+        let rendered = metrics.render();
+        let labels = "trigger=\"\",component=\"hello\"";
+        assert!(rendered.contains(&format!(
+            "spin_component_invocation_errors_total{{{labels}}} 1\n"
+        )));
+        let histogram = "spin_component_instantiation_duration_seconds";
+        assert!(rendered.contains(&format!("# TYPE {histogram} histogram\n")));
+        assert!(rendered.contains(&format!("{histogram}_bucket{{{labels},le=\"0.025\"}} 1\n")));
+        assert!(rendered.contains(&format!("{histogram}_count{{{labels}}} 1\n")));
+    }
+
+    #[test]
+    fn renders_invocation_duration_histogram() {
+        let metrics = Metrics::default();
+        metrics.record_invocation_duration("hello", Duration::from_micros(15_625));
+        metrics.record_invocation_duration("hello", Duration::from_secs(30));
+
+        let rendered = metrics.render();
+        let histogram = "spin_component_invocation_duration_seconds";
+        assert!(rendered.contains(&format!("# TYPE {histogram} histogram\n")));
+        let labels = "trigger=\"\",component=\"hello\"";
+        assert!(rendered.contains(&format!("{histogram}_bucket{{{labels},le=\"0.01\"}} 0\n")));
+        assert!(rendered.contains(&format!("{histogram}_bucket{{{labels},le=\"0.025\"}} 1\n")));
+        assert!(rendered.contains(&format!("{histogram}_bucket{{{labels},le=\"10\"}} 1\n")));
+        assert!(rendered.contains(&format!("{histogram}_bucket{{{labels},le=\"+Inf\"}} 2\n")));
+        assert!(rendered.contains(&format!("{histogram}_sum{{{labels}}} 30.015625\n")));
+        assert!(rendered.contains(&format!("{histogram}_count{{{labels}}} 2\n")));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape_label_value(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+}
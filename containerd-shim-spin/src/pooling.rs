@@ -0,0 +1,278 @@
+use std::{env, str::FromStr};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use wasmtime::{InstanceAllocationStrategy, PoolingAllocationConfig};
+
+use crate::{constants, container_env::ContainerEnv};
+
+/// Default number of component instances the pool can hold concurrently
+const DEFAULT_INSTANCE_COUNT: u32 = 1_000;
+/// Default number of core instances per component instance in the pool, as
+/// components usually instantiate adapter and shim modules next to their own
+const DEFAULT_CORE_INSTANCES_PER_COMPONENT: u32 = 8;
+/// Default number of linear memories per component instance in the pool
+const DEFAULT_MEMORIES_PER_COMPONENT: u32 = 2;
+/// Default number of tables per component instance in the pool
+const DEFAULT_TABLES_PER_COMPONENT: u32 = 4;
+/// Default maximum size in bytes of a linear memory in the pool
+const DEFAULT_MAX_MEMORY_SIZE: usize = 128 * 1024 * 1024;
+/// Default maximum number of elements of a table in the pool
+const DEFAULT_TABLE_ELEMENTS: usize = 100_000;
+
+/// How the Wasmtime instance allocator of the triggers is configured, as chosen
+/// by [`constants::SPIN_POOLING_ALLOCATOR_ENV`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum AllocatorMode {
+    /// Keep Spin's default allocation strategy
+    Default,
+    /// Use the on-demand allocator
+    OnDemand,
+    /// Use the pooling allocator with the given limits
+    Pooling(PoolingLimits),
+}
+
+/// Limits of the pooling instance allocator.
+///
+/// The virtual memory reserved for each memory slot is configured for the whole
+/// shim by [`constants::SPIN_MEMORY_RESERVATION_ENV`], as it must match the
+/// configuration components were precompiled with.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PoolingLimits {
+    instance_count: u32,
+    core_instance_count: u32,
+    memory_count: u32,
+    table_count: u32,
+    max_memory_size: usize,
+    table_elements: usize,
+}
+
+impl AllocatorMode {
+    pub(crate) fn from_env(env: &ContainerEnv) -> Result<Self> {
+        let enabled = match env.var(constants::SPIN_POOLING_ALLOCATOR_ENV) {
+            Some(value) => value.parse::<bool>().with_context(|| {
+                format!(
+                    "invalid value {value:?} for {}: expected true or false",
+                    constants::SPIN_POOLING_ALLOCATOR_ENV
+                )
+            })?,
+            None => return Ok(Self::Default),
+        };
+        if !enabled {
+            return Ok(Self::OnDemand);
+        }
+        let instance_count = parse_env(
+            env,
+            constants::SPIN_POOLING_INSTANCE_COUNT_ENV,
+            DEFAULT_INSTANCE_COUNT,
+        )?;
+        Ok(Self::Pooling(PoolingLimits {
+            instance_count,
+            core_instance_count: parse_env(
+                env,
+                constants::SPIN_POOLING_CORE_INSTANCE_COUNT_ENV,
+                instance_count.saturating_mul(DEFAULT_CORE_INSTANCES_PER_COMPONENT),
+            )?,
+            memory_count: parse_env(
+                env,
+                constants::SPIN_POOLING_MEMORY_COUNT_ENV,
+                instance_count.saturating_mul(DEFAULT_MEMORIES_PER_COMPONENT),
+            )?,
+            table_count: parse_env(
+                env,
+                constants::SPIN_POOLING_TABLE_COUNT_ENV,
+                instance_count.saturating_mul(DEFAULT_TABLES_PER_COMPONENT),
+            )?,
+            max_memory_size: parse_env(
+                env,
+                constants::SPIN_POOLING_MAX_MEMORY_SIZE_ENV,
+                DEFAULT_MAX_MEMORY_SIZE,
+            )?,
+            table_elements: parse_env(
+                env,
+                constants::SPIN_POOLING_TABLE_ELEMENTS_ENV,
+                DEFAULT_TABLE_ELEMENTS,
+            )?,
+        }))
+    }
+
+    /// Falls back to the on-demand allocator if the node cannot reserve the
+    /// virtual memory the pool needs.
+    pub(crate) fn or_fallback(self) -> Self {
+        match self {
+            Self::Pooling(limits) => match limits.probe() {
+                Ok(()) => {
+                    info!(" >>> using the pooling instance allocator with {limits:?}");
+                    Self::Pooling(limits)
+                }
+                Err(err) => {
+                    warn!(
+                        "unable to reserve virtual memory for the pooling instance allocator with {limits:?}, falling back to on-demand allocation: {err:#}"
+                    );
+                    Self::OnDemand
+                }
+            },
+            mode => mode,
+        }
+    }
+
+    /// Applies the allocation strategy and the memory reservation of the shim to
+    /// the engine configuration of a trigger.
+    pub(crate) fn configure(&self, config: &mut spin_core::Config) {
+        configure_memory_reservation(config.wasmtime_config());
+        match self {
+            Self::Default => {}
+            Self::OnDemand => {
+                config.disable_pooling();
+            }
+            Self::Pooling(limits) => limits.configure(config),
+        }
+    }
+}
+
+impl PoolingLimits {
+    fn configure(&self, config: &mut spin_core::Config) {
+        let mut pooling = PoolingAllocationConfig::default();
+        pooling
+            .total_component_instances(self.instance_count)
+            .total_core_instances(self.core_instance_count)
+            .total_memories(self.memory_count)
+            .total_tables(self.table_count)
+            .max_memory_size(self.max_memory_size)
+            .table_elements(self.table_elements);
+        config
+            .wasmtime_config()
+            .allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+    }
+
+    /// Creating an engine reserves the virtual memory of the whole pool, so this
+    /// fails if the node cannot provide it. The engine is configured like the one
+    /// of a trigger.
+    fn probe(&self) -> Result<()> {
+        let mut config = spin_core::Config::default();
+        configure_memory_reservation(config.wasmtime_config());
+        self.configure(&mut config);
+        wasmtime::Engine::new(config.wasmtime_config()).map(|_| ())
+    }
+}
+
+/// Returns the virtual memory in bytes to reserve for each linear memory, as
+/// configured by [`constants::SPIN_MEMORY_RESERVATION_ENV`] on the shim process.
+fn memory_reservation() -> Option<u64> {
+    let value = env::var(constants::SPIN_MEMORY_RESERVATION_ENV).ok()?;
+    match value.trim().parse::<u64>() {
+        Ok(reservation) => Some(reservation),
+        Err(_) => {
+            warn!(
+                "ignoring invalid value {value:?} for {}: expected a size in bytes",
+                constants::SPIN_MEMORY_RESERVATION_ENV
+            );
+            None
+        }
+    }
+}
+
+/// Applies the memory reservation configured for the shim, if any, to an engine
+/// configuration. Both the engine precompiling components and the engines of the
+/// triggers loading them are configured this way, as compiled code depends on it.
+pub(crate) fn configure_memory_reservation(config: &mut wasmtime::Config) {
+    if let Some(reservation) = memory_reservation() {
+        config.static_memory_maximum_size(reservation);
+    }
+}
+
+fn parse_env<T: FromStr>(env: &ContainerEnv, name: &str, default: T) -> Result<T> {
+    match env.var(name) {
+        Some(value) => value
+            .trim()
+            .parse::<T>()
+            .map_err(|_| anyhow::anyhow!("invalid value {value:?} for {name}")),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_mode_when_unset() {
+        let env = ContainerEnv::default();
+        assert_eq!(
+            AllocatorMode::from_env(&env).unwrap(),
+            AllocatorMode::Default
+        );
+        let env = ContainerEnv::from_iter([(constants::SPIN_POOLING_ALLOCATOR_ENV, "false")]);
+        assert_eq!(
+            AllocatorMode::from_env(&env).unwrap(),
+            AllocatorMode::OnDemand
+        );
+    }
+
+    #[test]
+    fn pooling_limits_from_env() {
+        let env = ContainerEnv::from_iter([
+            (constants::SPIN_POOLING_ALLOCATOR_ENV, "true"),
+            (constants::SPIN_POOLING_INSTANCE_COUNT_ENV, "2"),
+            (constants::SPIN_POOLING_MAX_MEMORY_SIZE_ENV, "65536"),
+        ]);
+        let mode = AllocatorMode::from_env(&env).unwrap();
+        assert_eq!(
+            mode,
+            AllocatorMode::Pooling(PoolingLimits {
+                instance_count: 2,
+                core_instance_count: 2 * DEFAULT_CORE_INSTANCES_PER_COMPONENT,
+                memory_count: 2 * DEFAULT_MEMORIES_PER_COMPONENT,
+                table_count: 2 * DEFAULT_TABLES_PER_COMPONENT,
+                max_memory_size: 65536,
+                table_elements: DEFAULT_TABLE_ELEMENTS,
+            })
+        );
+        // A pool this small can always be reserved
+        assert_eq!(mode.clone().or_fallback(), mode);
+    }
+
+    #[test]
+    fn pooling_totals_from_env() {
+        let env = ContainerEnv::from_iter([
+            (constants::SPIN_POOLING_ALLOCATOR_ENV, "true"),
+            (constants::SPIN_POOLING_INSTANCE_COUNT_ENV, "10"),
+            (constants::SPIN_POOLING_CORE_INSTANCE_COUNT_ENV, "50"),
+            (constants::SPIN_POOLING_MEMORY_COUNT_ENV, "20"),
+            (constants::SPIN_POOLING_TABLE_COUNT_ENV, "30"),
+        ]);
+        let AllocatorMode::Pooling(limits) = AllocatorMode::from_env(&env).unwrap() else {
+            panic!("expected the pooling allocator");
+        };
+        assert_eq!(limits.instance_count, 10);
+        assert_eq!(limits.core_instance_count, 50);
+        assert_eq!(limits.memory_count, 20);
+        assert_eq!(limits.table_count, 30);
+    }
+
+    #[test]
+    fn memory_reservation_from_shim_env() {
+        temp_env::with_var(constants::SPIN_MEMORY_RESERVATION_ENV, None::<&str>, || {
+            assert_eq!(memory_reservation(), None);
+        });
+        temp_env::with_var(
+            constants::SPIN_MEMORY_RESERVATION_ENV,
+            Some("1073741824"),
+            || {
+                assert_eq!(memory_reservation(), Some(1 << 30));
+            },
+        );
+        temp_env::with_var(constants::SPIN_MEMORY_RESERVATION_ENV, Some("1GiB"), || {
+            assert_eq!(memory_reservation(), None);
+        });
+    }
+
+    #[test]
+    fn rejects_invalid_limits() {
+        let env = ContainerEnv::from_iter([
+            (constants::SPIN_POOLING_ALLOCATOR_ENV, "true"),
+            (constants::SPIN_POOLING_INSTANCE_COUNT_ENV, "many"),
+        ]);
+        assert!(AllocatorMode::from_env(&env).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use toml::{Table, Value};
+
+use crate::{constants, container_env::ContainerEnv};
+
+/// Prefix of placeholders resolved to the content of a file
+const FILE_PLACEHOLDER_PREFIX: &str = "file:";
+/// Runtime config tables configuring application variable providers
+const VARIABLES_PROVIDER_KEYS: &[&str] = &["variables_provider", "config_provider"];
+
+/// The runtime config of an application, resolved from [`constants::RUNTIME_CONFIG_PATH`]
+/// and the fragments in [`constants::RUNTIME_CONFIG_DIR`] once per container.
+///
+/// Fragments are merged in the lexical order of their file names on top of the
+/// runtime config, tables being merged key by key. `${VAR}` placeholders in
+/// string values are replaced with the value of the container environment variable `VAR`
+/// and `${file:/path}` placeholders with the content of the file, such as a
+/// mounted Secret. `$${` escapes a literal `${`.
+///
+/// The resolved config, which may hold secrets, is kept in memory and only
+/// written to disk by [`ResolvedRuntimeConfig::write`] while Spin reads it.
+#[derive(Default)]
+pub(crate) struct ResolvedRuntimeConfig(Option<String>);
+
+impl ResolvedRuntimeConfig {
+    pub(crate) fn resolve(env: &ContainerEnv) -> Result<Self> {
+        resolve_from(
+            env,
+            Path::new(constants::RUNTIME_CONFIG_PATH),
+            Path::new(constants::RUNTIME_CONFIG_DIR),
+        )
+        .map(Self)
+    }
+
+    /// Returns the path of the resolved runtime config to hand to Spin, if any was found.
+    pub(crate) fn path(&self) -> Option<PathBuf> {
+        self.0
+            .as_ref()
+            .map(|_| PathBuf::from(constants::RUNTIME_CONFIG_RESOLVED_PATH))
+    }
+
+    /// Writes the resolved runtime config to [`ResolvedRuntimeConfig::path`], for
+    /// Spin to read as it builds the factors of a trigger. The file is removed when
+    /// the returned [`ResolvedFile`] is dropped.
+    ///
+    /// Spin only reads the runtime config from a file, so the resolved config is
+    /// written next to [`constants::RUNTIME_CONFIG_PATH`], keeping relative paths
+    /// valid, and is only readable by its owner.
+    pub(crate) fn write(&self) -> Result<Option<ResolvedFile>> {
+        match self.0.as_deref() {
+            Some(config) => {
+                write_to(config, Path::new(constants::RUNTIME_CONFIG_RESOLVED_PATH)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// The resolved runtime config on disk, removed when dropped.
+pub(crate) struct ResolvedFile(PathBuf);
+
+impl Drop for ResolvedFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.0) {
+            warn!(
+                "failed to remove resolved runtime config {}: {err}",
+                self.0.display()
+            );
+        }
+    }
+}
+
+fn write_to(config: &str, path: &Path) -> Result<ResolvedFile> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let resolved = ResolvedFile(path.to_path_buf());
+    file.write_all(config.as_bytes())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(resolved)
+}
+
+/// Returns whether the runtime config configures application variable providers,
+/// such as Vault, which the shim cannot query before Spin starts.
+pub(crate) fn configures_variables_providers() -> Result<bool> {
+    let config = load(
+        Path::new(constants::RUNTIME_CONFIG_PATH),
+        Path::new(constants::RUNTIME_CONFIG_DIR),
+    )?;
+    Ok(config.is_some_and(|config| has_variables_providers(&config)))
+}
+
+fn has_variables_providers(config: &Table) -> bool {
+    VARIABLES_PROVIDER_KEYS
+        .iter()
+        .any(|key| config.contains_key(*key))
+}
+
+fn resolve_from(
+    env: &ContainerEnv,
+    config_path: &Path,
+    fragments_dir: &Path,
+) -> Result<Option<String>> {
+    let Some(mut config) = load(config_path, fragments_dir)? else {
+        return Ok(None);
+    };
+    interpolate_table(env, &mut config).context("failed to resolve runtime config placeholders")?;
+    Ok(Some(config.to_string()))
+}
+
+/// Merges the runtime config and its fragments, without resolving placeholders.
+fn load(config_path: &Path, fragments_dir: &Path) -> Result<Option<Table>> {
+    let mut sources = Vec::new();
+    if config_path.exists() {
+        sources.push(config_path.to_path_buf());
+    }
+    if fragments_dir.is_dir() {
+        let mut fragments = fs::read_dir(fragments_dir)
+            .with_context(|| format!("failed to read {}", fragments_dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()
+            .with_context(|| format!("failed to read {}", fragments_dir.display()))?;
+        fragments
+            .retain(|path| path.extension().is_some_and(|ext| ext == "toml") && path.is_file());
+        fragments.sort();
+        sources.extend(fragments);
+    }
+    if sources.is_empty() {
+        return Ok(None);
+    }
+
+    let mut config = Table::new();
+    for source in &sources {
+        let content = fs::read_to_string(source)
+            .with_context(|| format!("failed to read runtime config {}", source.display()))?;
+        let fragment: Table = content
+            .parse()
+            .with_context(|| format!("invalid runtime config {}", source.display()))?;
+        merge(&mut config, fragment);
+    }
+    Ok(Some(config))
+}
+
+/// Merges `fragment` into `config`, recursing into tables present in both.
+fn merge(config: &mut Table, fragment: Table) {
+    for (key, value) in fragment {
+        match (config.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => merge(existing, table),
+            (_, value) => {
+                config.insert(key, value);
+            }
+        }
+    }
+}
+
+fn interpolate_table(env: &ContainerEnv, table: &mut Table) -> Result<()> {
+    for value in table.values_mut() {
+        interpolate_value(env, value)?;
+    }
+    Ok(())
+}
+
+fn interpolate_value(env: &ContainerEnv, value: &mut Value) -> Result<()> {
+    match value {
+        Value::String(s) => *s = interpolate(env, s)?,
+        Value::Array(values) => {
+            for value in values {
+                interpolate_value(env, value)?;
+            }
+        }
+        Value::Table(table) => interpolate_table(env, table)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate(env: &ContainerEnv, s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = escaped;
+        } else if let Some(placeholder) = rest.strip_prefix("${") {
+            let Some(end) = placeholder.find('}') else {
+                bail!("unterminated placeholder in {s:?}");
+            };
+            out.push_str(&resolve_placeholder(env, placeholder[..end].trim())?);
+            rest = &placeholder[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn resolve_placeholder(env: &ContainerEnv, name: &str) -> Result<String> {
+    if let Some(path) = name.strip_prefix(FILE_PLACEHOLDER_PREFIX) {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read file {path} referenced by placeholder"))?;
+        return Ok(content.trim_end_matches(['\r', '\n']).to_string());
+    }
+    if name.is_empty() {
+        bail!("empty placeholder");
+    }
+    env.var(name).map(str::to_string).with_context(|| {
+        format!("environment variable {name} referenced by placeholder is not set")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn interpolates_placeholders() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("password");
+        fs::write(&secret, "s3cret\n").unwrap();
+
+        let env = ContainerEnv::from_iter([("REDIS_HOST", "redis.default.svc")]);
+        assert_eq!(
+            interpolate(&env, "redis://${REDIS_HOST}:6379").unwrap(),
+            "redis://redis.default.svc:6379"
+        );
+        assert_eq!(
+            interpolate(&env, &format!("${{file:{}}}", secret.display())).unwrap(),
+            "s3cret"
+        );
+        assert_eq!(
+            interpolate(&env, "cost: $5, $${literal}").unwrap(),
+            "cost: $5, ${literal}"
+        );
+        assert!(interpolate(&env, "${REDIS_HOST").is_err());
+
+        let err = interpolate(&ContainerEnv::default(), "${REDIS_HOST}").unwrap_err();
+        assert!(err.to_string().contains("REDIS_HOST"));
+    }
+
+    #[test]
+    fn merges_fragments_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("runtime-config.toml");
+        let fragments_dir = dir.path().join("runtime-config.d");
+        fs::create_dir(&fragments_dir).unwrap();
+        fs::write(
+            &config_path,
+            r#"
+            [key_value_store.default]
+            type = "redis"
+            url = "redis://localhost"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            fragments_dir.join("20-kv.toml"),
+            r#"
+            [key_value_store.default]
+            url = "redis://${KV_HOST}"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            fragments_dir.join("10-sqlite.toml"),
+            r#"
+            [sqlite_database.default]
+            type = "spin"
+            path = "/data/db"
+            "#,
+        )
+        .unwrap();
+        fs::write(fragments_dir.join("README.md"), "ignored").unwrap();
+
+        let env = ContainerEnv::from_iter([("KV_HOST", "kv.svc")]);
+        let resolved = resolve_from(&env, &config_path, &fragments_dir)
+            .unwrap()
+            .unwrap();
+        let config: Table = resolved.parse().unwrap();
+        let kv = &config["key_value_store"]["default"];
+        assert_eq!(kv["type"].as_str(), Some("redis"));
+        assert_eq!(kv["url"].as_str(), Some("redis://kv.svc"));
+        assert_eq!(
+            config["sqlite_database"]["default"]["path"].as_str(),
+            Some("/data/db")
+        );
+    }
+
+    #[test]
+    fn detects_variables_providers() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("runtime-config.toml");
+        let fragments_dir = dir.path().join("runtime-config.d");
+        fs::create_dir(&fragments_dir).unwrap();
+        fs::write(&config_path, "[key_value_store.default]\ntype = \"spin\"\n").unwrap();
+        let config = load(&config_path, &fragments_dir).unwrap().unwrap();
+        assert!(!has_variables_providers(&config));
+
+        fs::write(
+            fragments_dir.join("vault.toml"),
+            "[[variables_provider]]\ntype = \"vault\"\nurl = \"${VAULT_ADDR}\"\n",
+        )
+        .unwrap();
+        let config = load(&config_path, &fragments_dir).unwrap().unwrap();
+        assert!(has_variables_providers(&config));
+    }
+
+    #[test]
+    fn no_runtime_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let resolved = resolve_from(
+            &ContainerEnv::default(),
+            &dir.path().join("runtime-config.toml"),
+            &dir.path().join("runtime-config.d"),
+        )
+        .unwrap();
+        assert!(resolved.is_none());
+    }
+
+    #[test]
+    fn removes_resolved_file_once_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("resolved.toml");
+        let resolved = write_to("[key_value_store.default]\ntype = \"spin\"\n", &path).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        drop(resolved);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    convert::Infallible,
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::fs::FileTypeExt,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use http::{Request, Response};
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use socket2::{Domain, Socket, Type};
+use tokio::{
+    io::{copy_bidirectional, AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixListener},
+    task::JoinHandle,
+};
+
+use crate::{constants, utils::ListenAddr};
+
+/// Handle to a server started by the shim next to the Spin application.
+/// The server is stopped when the handle is dropped.
+pub(crate) struct ServerHandle(JoinHandle<()>);
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Starts an HTTP/1 server on the given address that answers every request
+/// with the response built by `respond` from the request path.
+pub(crate) async fn serve<F>(addr: SocketAddr, name: &str, respond: F) -> Result<ServerHandle>
+where
+    F: Fn(&str) -> Response<Full<Bytes>> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind {name} listener to {addr}"))?;
+    info!(" >>> serving {name} on {addr}");
+    let name = name.to_string();
+    let respond = Arc::new(respond);
+    let handle = tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!("failed to accept {name} connection: {err}");
+                    continue;
+                }
+            };
+            let name = name.clone();
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let response = respond(req.uri().path());
+                    async move { Ok::<_, Infallible>(response) }
+                });
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    warn!("failed to serve {name} request: {err}");
+                }
+            });
+        }
+    });
+    Ok(ServerHandle(handle))
+}
+
+/// Accepts connections on `addr` and forwards them to the HTTP trigger listening
+/// on `target`.
+///
+/// Connections are forwarded byte for byte, which keeps TLS end to end but leaves
+/// no way to pass the client address on, as Spin neither understands the PROXY
+/// protocol nor can headers be added to encrypted requests. Forwarded requests
+/// therefore reach the trigger from a local address, which is the client address
+/// Spin reports to components.
+pub(crate) fn forward(addr: &ListenAddr, target: SocketAddr) -> Result<ServerHandle> {
+    let handle = match addr {
+        ListenAddr::Tcp(addr) => {
+            let listener = TcpListener::from_std(bind_tcp(*addr)?)?;
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(proxy(stream, target));
+                        }
+                        Err(err) => warn!("failed to accept connection: {err}"),
+                    }
+                }
+            })
+        }
+        ListenAddr::Unix(path) => {
+            let listener = bind_unix(path)?;
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(proxy(stream, target));
+                        }
+                        Err(err) => warn!("failed to accept connection: {err}"),
+                    }
+                }
+            })
+        }
+    };
+    warn!(
+        " >>> forwarding {addr} to the HTTP trigger on {target}: components see the shim as the client of these connections"
+    );
+    Ok(ServerHandle(handle))
+}
+
+/// A loopback port reserved for a listener the shim does not bind itself.
+///
+/// The reserving socket is bound with `SO_REUSEADDR` but never listens, so a
+/// listener that also sets `SO_REUSEADDR`, as Spin's does, can still bind and
+/// listen on the port, while other processes cannot be assigned it. The port
+/// stays reserved until the reservation is dropped.
+pub(crate) struct PortReservation {
+    _socket: Socket,
+    pub(crate) addr: SocketAddr,
+}
+
+/// Reserves a free port on the IPv4 loopback address.
+pub(crate) fn reserve_loopback_port() -> Result<PortReservation> {
+    let reserve = || -> std::io::Result<PortReservation> {
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None)?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into())?;
+        let addr = socket
+            .local_addr()?
+            .as_socket()
+            .ok_or_else(|| std::io::Error::other("not an IP socket"))?;
+        Ok(PortReservation {
+            _socket: socket,
+            addr,
+        })
+    };
+    reserve().context("failed to reserve a loopback port")
+}
+
+/// Waits until a server accepts connections on `addr`.
+pub(crate) async fn wait_until_listening(addr: SocketAddr) {
+    while TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(
+            constants::TRIGGER_LISTENING_POLL_INTERVAL_MS,
+        ))
+        .await;
+    }
+}
+
+/// Binds a TCP listener, restricting IPv6 listeners to IPv6 so that they can
+/// sit next to an IPv4 listener on the same port.
+fn bind_tcp(addr: SocketAddr) -> Result<std::net::TcpListener> {
+    let bind = || -> std::io::Result<std::net::TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket.into())
+    };
+    bind().with_context(|| format!("failed to bind listener to {addr}"))
+}
+
+/// Binds a Unix domain socket, replacing a stale socket left by a previous run.
+fn bind_unix(path: &Path) -> Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    UnixListener::bind(path)
+        .with_context(|| format!("failed to bind listener to unix:{}", path.display()))
+}
+
+async fn proxy<S>(mut stream: S, target: SocketAddr)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let result = match TcpStream::connect(target).await {
+        Ok(mut upstream) => copy_bidirectional(&mut stream, &mut upstream)
+            .await
+            .map(|_| ()),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        debug!("failed to forward connection to {target}: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Starts a server echoing back everything it receives.
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+        addr
+    }
+
+    async fn assert_echoes<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) {
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn forwards_tcp_connections() {
+        let target = echo_server().await;
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let _handle = forward(&ListenAddr::Tcp(addr), target).unwrap();
+        assert_echoes(TcpStream::connect(addr).await.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn waits_until_listening() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listening = tokio::spawn(wait_until_listening(addr));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!listening.is_finished());
+
+        let _listener = TcpListener::bind(addr).await.unwrap();
+        listening.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reserved_port_can_only_be_bound_by_reusing_listeners() {
+        let reservation = reserve_loopback_port().unwrap();
+        let addr = reservation.addr;
+        assert!(addr.ip().is_loopback());
+
+        let exclusive = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        assert!(exclusive.bind(&addr.into()).is_err());
+
+        // Like Spin's listener, tokio listeners set SO_REUSEADDR
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let accepted = tokio::spawn(async move { listener.accept().await.unwrap() });
+        TcpStream::connect(addr).await.unwrap();
+        accepted.await.unwrap();
+        drop(reservation);
+    }
+
+    #[tokio::test]
+    async fn forwards_unix_socket_connections() {
+        let target = echo_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("http.sock");
+        // A stale socket from a previous run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let _handle = forward(&ListenAddr::Unix(path.clone()), target).unwrap();
+        assert_echoes(tokio::net::UnixStream::connect(&path).await.unwrap()).await;
+    }
+}
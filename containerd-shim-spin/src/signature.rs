@@ -0,0 +1,359 @@
+use std::{env, fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use log::{debug, error, info, warn};
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+};
+
+use crate::{constants, container_env::ContainerEnv};
+
+/// What happens when an application is unsigned or its signature is invalid,
+/// ordered from the least to the most strict
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SignaturePolicy {
+    /// Signatures are not checked
+    #[default]
+    Off,
+    /// Failed verifications are logged and the application runs anyway
+    Warn,
+    /// Failed verifications prevent the application from running
+    Enforce,
+}
+
+impl FromStr for SignaturePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "enforce" => Ok(Self::Enforce),
+            other => bail!(
+                "invalid value {other:?} for {}: expected off, warn or enforce",
+                constants::SPIN_SIGNATURE_POLICY_ENV
+            ),
+        }
+    }
+}
+
+/// Verifies the signature of OCI applications according to the configured policy.
+///
+/// The signature covers the Spin application config layer. The config pins the
+/// digest of every wasm and data layer of the application, so a valid signature
+/// vouches for the whole application.
+///
+/// The policy and the public key are configured for the node by the environment
+/// of the shim process, the key being read from the host filesystem. Containers
+/// can only make the policy stricter (see [`SignatureVerifier::for_container`]).
+/// Applications loaded from [`constants::SPIN_MANIFEST_FILE_PATH`] are not signed,
+/// so they are refused when enforcing signatures.
+#[derive(Clone, Default)]
+pub(crate) struct SignatureVerifier {
+    policy: SignaturePolicy,
+    key: Option<VerifyingKey>,
+}
+
+impl SignatureVerifier {
+    /// The verifier configured by the environment of the shim process. As the
+    /// shim cannot fail to start, a configuration that cannot be loaded fails
+    /// the verification of every application instead.
+    pub(crate) fn from_node_env() -> Self {
+        Self::load(
+            env::var(constants::SPIN_SIGNATURE_POLICY_ENV)
+                .ok()
+                .as_deref(),
+            env::var(constants::SPIN_SIGNATURE_PUBLIC_KEY_ENV)
+                .ok()
+                .as_deref(),
+        )
+    }
+
+    fn load(policy: Option<&str>, key_path: Option<&str>) -> Self {
+        let policy = match policy.map(str::parse).transpose() {
+            Ok(policy) => policy.unwrap_or_default(),
+            Err(err) => {
+                error!("enforcing signatures of all applications: {err:#}");
+                SignaturePolicy::Enforce
+            }
+        };
+        let key_path = Path::new(key_path.unwrap_or(constants::SPIN_SIGNATURE_PUBLIC_KEY_DEFAULT));
+        // The key is loaded even if the node does not verify signatures, as
+        // containers may require them
+        let key = match read_key(key_path) {
+            Ok(key) => Some(key),
+            Err(err) if policy == SignaturePolicy::Off => {
+                debug!("not loading signature public key: {err:#}");
+                None
+            }
+            Err(err) => {
+                error!("failing signature verification of all applications: {err:#}");
+                None
+            }
+        };
+        Self { policy, key }
+    }
+
+    /// Applies the policy a container requests with
+    /// [`constants::SPIN_SIGNATURE_POLICY_ENV`], if it is stricter than the one
+    /// of the node. The public key is never taken from the container.
+    pub(crate) fn for_container(&self, env: &ContainerEnv) -> Result<Self> {
+        if env.var(constants::SPIN_SIGNATURE_PUBLIC_KEY_ENV).is_some() {
+            warn!(
+                "ignoring {} of the container: signature public keys are configured on the node",
+                constants::SPIN_SIGNATURE_PUBLIC_KEY_ENV
+            );
+        }
+        let policy = match env.var(constants::SPIN_SIGNATURE_POLICY_ENV) {
+            Some(policy) => policy.parse::<SignaturePolicy>()?.max(self.policy),
+            None => self.policy,
+        };
+        Ok(Self {
+            policy,
+            key: self.key,
+        })
+    }
+
+    /// Returns whether signatures are checked at all.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.policy != SignaturePolicy::Off
+    }
+
+    /// Checks the detached `signature` of the application config, applying the
+    /// policy to the outcome.
+    pub(crate) fn verify(
+        &self,
+        config: &[u8],
+        config_digest: &str,
+        signature: Option<&[u8]>,
+    ) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let verified = match &self.key {
+            Some(key) => check_signature(key, config, config_digest, signature),
+            None => Err(anyhow!(
+                "cannot verify application config {config_digest}: no signature public key is configured by {} of the shim",
+                constants::SPIN_SIGNATURE_PUBLIC_KEY_ENV
+            )),
+        };
+        match verified {
+            Ok(()) => {
+                info!(" >>> verified signature of application config {config_digest}");
+                Ok(())
+            }
+            Err(err) => self.apply_policy(err),
+        }
+    }
+
+    /// Applies the policy to an application loaded from a file manifest, whose
+    /// signature cannot be verified.
+    pub(crate) fn verify_file_app(&self, manifest_path: &Path) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        self.apply_policy(anyhow!(
+            "application loaded from {} is not signed: only the signature of OCI applications can be verified",
+            manifest_path.display()
+        ))
+    }
+
+    fn apply_policy(&self, err: anyhow::Error) -> Result<()> {
+        if self.policy == SignaturePolicy::Warn {
+            warn!("running application despite failed signature verification: {err:#}");
+            return Ok(());
+        }
+        Err(err)
+    }
+}
+
+fn read_key(key_path: &Path) -> Result<VerifyingKey> {
+    let pem = fs::read_to_string(key_path)
+        .with_context(|| format!("failed to read signature public key {}", key_path.display()))?;
+    VerifyingKey::from_public_key_pem(&pem)
+        .map_err(|err| anyhow!("invalid signature public key {}: {err}", key_path.display()))
+}
+
+fn check_signature(
+    key: &VerifyingKey,
+    config: &[u8],
+    config_digest: &str,
+    signature: Option<&[u8]>,
+) -> Result<()> {
+    let signature =
+        signature.ok_or_else(|| anyhow!("application config {config_digest} is not signed"))?;
+    let der = STANDARD
+        .decode(signature.trim_ascii())
+        .with_context(|| format!("malformed signature of application config {config_digest}"))?;
+    let signature = Signature::from_der(&der)
+        .with_context(|| format!("malformed signature of application config {config_digest}"))?;
+    key.verify(config, &signature).map_err(|_| {
+        anyhow!("signature of application config {config_digest} does not match the public key")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::{
+        ecdsa::{signature::Signer, SigningKey},
+        pkcs8::{EncodePublicKey, LineEnding},
+    };
+
+    use super::*;
+
+    const CONFIG: &[u8] = br#"{"spin_lock_version":1,"components":[],"triggers":[]}"#;
+    const DIGEST: &str = "sha256:0123";
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32].into()).unwrap()
+    }
+
+    fn sign(key: &SigningKey, payload: &[u8]) -> Vec<u8> {
+        let signature: Signature = key.sign(payload);
+        STANDARD.encode(signature.to_der()).into_bytes()
+    }
+
+    fn verifier(policy: SignaturePolicy, key: &SigningKey) -> SignatureVerifier {
+        SignatureVerifier {
+            policy,
+            key: Some(*key.verifying_key()),
+        }
+    }
+
+    #[test]
+    fn enforce_accepts_valid_signature() {
+        let key = signing_key(1);
+        let signature = sign(&key, CONFIG);
+        verifier(SignaturePolicy::Enforce, &key)
+            .verify(CONFIG, DIGEST, Some(&signature))
+            .unwrap();
+    }
+
+    #[test]
+    fn enforce_rejects_unsigned_and_tampered_apps() {
+        let key = signing_key(1);
+        let verifier = verifier(SignaturePolicy::Enforce, &key);
+
+        let err = verifier.verify(CONFIG, DIGEST, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "application config sha256:0123 is not signed"
+        );
+
+        let signature = sign(&signing_key(2), CONFIG);
+        let err = verifier
+            .verify(CONFIG, DIGEST, Some(&signature))
+            .unwrap_err();
+        assert!(err.to_string().contains("sha256:0123"));
+
+        let signature = sign(&key, b"other config");
+        assert!(verifier.verify(CONFIG, DIGEST, Some(&signature)).is_err());
+    }
+
+    #[test]
+    fn warn_allows_unsigned_apps() {
+        verifier(SignaturePolicy::Warn, &signing_key(1))
+            .verify(CONFIG, DIGEST, None)
+            .unwrap();
+    }
+
+    fn write_key(dir: &Path, key: &SigningKey) -> String {
+        let key_path = dir.join("cosign.pub");
+        let pem = key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        fs::write(&key_path, pem).unwrap();
+        key_path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn loads_node_policy_and_public_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = signing_key(1);
+        let key_path = write_key(dir.path(), &key);
+
+        let verifier = SignatureVerifier::load(Some("enforce"), Some(&key_path));
+        assert_eq!(verifier.policy, SignaturePolicy::Enforce);
+        verifier
+            .verify(CONFIG, DIGEST, Some(&sign(&key, CONFIG)))
+            .unwrap();
+
+        let verifier = SignatureVerifier::load(None, Some(&key_path));
+        assert_eq!(verifier.policy, SignaturePolicy::Off);
+        verifier.verify(CONFIG, DIGEST, None).unwrap();
+    }
+
+    #[test]
+    fn fails_closed_on_invalid_node_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = signing_key(1);
+        let signature = sign(&key, CONFIG);
+
+        let missing_key = dir.path().join("missing.pub");
+        let verifier = SignatureVerifier::load(Some("warn"), missing_key.to_str());
+        verifier.verify(CONFIG, DIGEST, Some(&signature)).unwrap();
+        let verifier = SignatureVerifier::load(Some("enforce"), missing_key.to_str());
+        assert!(verifier.verify(CONFIG, DIGEST, Some(&signature)).is_err());
+
+        let key_path = write_key(dir.path(), &key);
+        let verifier = SignatureVerifier::load(Some("strict"), Some(&key_path));
+        assert_eq!(verifier.policy, SignaturePolicy::Enforce);
+    }
+
+    #[test]
+    fn containers_can_only_make_the_policy_stricter() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = signing_key(1);
+        let node = verifier(SignaturePolicy::Warn, &key);
+
+        let env = ContainerEnv::from_iter([(constants::SPIN_SIGNATURE_POLICY_ENV, "off")]);
+        let container = node.for_container(&env).unwrap();
+        assert_eq!(container.policy, SignaturePolicy::Warn);
+
+        let env = ContainerEnv::from_iter([(constants::SPIN_SIGNATURE_POLICY_ENV, "enforce")]);
+        let container = node.for_container(&env).unwrap();
+        assert_eq!(container.policy, SignaturePolicy::Enforce);
+        assert!(container.verify(CONFIG, DIGEST, None).is_err());
+
+        // The public key of the container is ignored
+        let other = signing_key(2);
+        let env = ContainerEnv::from_iter([
+            (constants::SPIN_SIGNATURE_POLICY_ENV, "enforce"),
+            (
+                constants::SPIN_SIGNATURE_PUBLIC_KEY_ENV,
+                &write_key(dir.path(), &other),
+            ),
+        ]);
+        let container = node.for_container(&env).unwrap();
+        assert!(container
+            .verify(CONFIG, DIGEST, Some(&sign(&other, CONFIG)))
+            .is_err());
+
+        // Without a key on the node, requiring signatures fails every application
+        let env = ContainerEnv::from_iter([(constants::SPIN_SIGNATURE_POLICY_ENV, "enforce")]);
+        let container = SignatureVerifier::default().for_container(&env).unwrap();
+        assert!(container
+            .verify(CONFIG, DIGEST, Some(&sign(&key, CONFIG)))
+            .is_err());
+
+        let env = ContainerEnv::from_iter([(constants::SPIN_SIGNATURE_POLICY_ENV, "maybe")]);
+        assert!(node.for_container(&env).is_err());
+    }
+
+    #[test]
+    fn applies_policy_to_file_apps() {
+        let path = Path::new(constants::SPIN_MANIFEST_FILE_PATH);
+        let key = signing_key(1);
+        SignatureVerifier::default().verify_file_app(path).unwrap();
+        verifier(SignaturePolicy::Warn, &key)
+            .verify_file_app(path)
+            .unwrap();
+        let err = verifier(SignaturePolicy::Enforce, &key)
+            .verify_file_app(path)
+            .unwrap_err();
+        assert!(err.to_string().contains("/spin.toml is not signed"));
+    }
+}
@@ -1,34 +1,102 @@
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Component, Path, PathBuf},
+};
 
-use anyhow::{Context, Result};
-use containerd_shim_wasm::container::RuntimeContext;
+use anyhow::{bail, Context, Result};
+use containerd_shim_wasm::{container::RuntimeContext, sandbox::WasmLayer};
 use log::info;
 use oci_spec::image::MediaType;
 use spin_app::locked::LockedApp;
 use spin_loader::{cache::Cache, FilesMountStrategy};
+use spin_manifest::schema::{common::WasiFilesMount, v2::ComponentSource};
 
-use crate::{constants, utils::handle_archive_layer};
+use crate::{
+    constants,
+    container_env::ContainerEnv,
+    signature::SignatureVerifier,
+    utils::{handle_archive_layer, verify_layer_digest},
+};
 
 #[derive(Clone)]
 pub enum Source {
     File(PathBuf),
-    Oci,
+    Oci(OciImage),
+}
+
+/// The OCI image a Spin application was distributed with
+#[derive(Clone, Debug)]
+pub struct OciImage {
+    /// Image reference as configured by [`constants::SPIN_OCI_IMAGE_REFERENCE_ENV`].
+    /// Runwasi does not pass the image name CRI annotates the container with
+    /// (`io.kubernetes.cri.image-name`) on to engines, so it is unknown otherwise.
+    pub(crate) reference: Option<String>,
+    /// Digest of the Spin application config layer
+    pub(crate) config_digest: Option<String>,
+}
+
+impl OciImage {
+    /// The image of the given layers, referenced as configured by
+    /// [`constants::SPIN_OCI_IMAGE_REFERENCE_ENV`].
+    pub(crate) fn new(env: &ContainerEnv, layers: &[WasmLayer]) -> Self {
+        let config = config_layer(layers);
+        Self {
+            reference: env
+                .var(constants::SPIN_OCI_IMAGE_REFERENCE_ENV)
+                .map(str::to_string),
+            config_digest: config.map(|config| config.config.digest().to_string()),
+        }
+    }
+
+    /// Name of the image, i.e. its reference without a manifest digest.
+    pub(crate) fn name(&self) -> Option<&str> {
+        let reference = self.reference.as_deref()?;
+        Some(
+            reference
+                .split_once('@')
+                .map_or(reference, |(name, _)| name),
+        )
+    }
+
+    /// Digest of the image manifest if the reference is pinned to one, as in
+    /// `ghcr.io/spinkube/spin-rust-hello@sha256:...`. The shim is not told the
+    /// manifest digest of the image otherwise.
+    pub(crate) fn manifest_digest(&self) -> Option<&str> {
+        self.reference
+            .as_deref()?
+            .split_once('@')
+            .map(|(_, digest)| digest)
+            .filter(|digest| digest.contains(':'))
+    }
 }
 
 impl std::fmt::Debug for Source {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Source::File(path) => write!(f, "File({})", path.display()),
-            Source::Oci => write!(f, "Oci"),
+            Source::Oci(image) => write!(
+                f,
+                "Oci({})",
+                image.reference.as_deref().unwrap_or("unknown reference")
+            ),
         }
     }
 }
 
 impl Source {
-    pub(crate) async fn from_ctx(ctx: &impl RuntimeContext, cache: &Cache) -> Result<Self> {
+    pub(crate) async fn from_ctx(
+        ctx: &impl RuntimeContext,
+        env: &ContainerEnv,
+        cache: &Cache,
+        wasmtime_engine: &wasmtime::Engine,
+        verifier: &SignatureVerifier,
+    ) -> Result<Self> {
         match ctx.entrypoint().source {
             containerd_shim_wasm::container::Source::File(_) => {
-                Ok(Source::File(constants::SPIN_MANIFEST_FILE_PATH.into()))
+                let manifest_path = PathBuf::from(constants::SPIN_MANIFEST_FILE_PATH);
+                verifier.verify_file_app(&manifest_path)?;
+                Ok(Source::File(manifest_path))
             }
             containerd_shim_wasm::container::Source::Oci(layers) => {
                 info!(" >>> configuring spin oci application {}", layers.len());
@@ -37,6 +105,8 @@ impl Source {
                     log::debug!("<<< layer config: {:?}", layer.config);
                 }
 
+                verify_signature(verifier, layers)?;
+
                 for artifact in layers {
                     match artifact.config.media_type() {
                         MediaType::Other(name)
@@ -50,6 +120,14 @@ impl Source {
                                 .context("failed to write spin.json")?;
                         }
                         MediaType::Other(name) if name == constants::OCI_LAYER_MEDIA_TYPE_WASM => {
+                            // Precompiled layers no longer hash to the digest of the
+                            // wasm they were compiled from
+                            if wasmtime_engine
+                                .detect_precompiled(&artifact.layer)
+                                .is_none()
+                            {
+                                verify_layer_digest(artifact)?;
+                            }
                             log::info!(
                                 "<<< writing wasm artifact with length {:?} config to cache, near {:?}",
                                 artifact.layer.len(),
@@ -60,6 +138,7 @@ impl Source {
                                 .await?;
                         }
                         MediaType::Other(name) if name == spin_oci::client::DATA_MEDIATYPE => {
+                            verify_layer_digest(artifact)?;
                             log::debug!(
                                 "<<< writing data layer to cache, near {:?}",
                                 cache.manifests_dir()
@@ -69,6 +148,7 @@ impl Source {
                                 .await?;
                         }
                         MediaType::Other(name) if name == spin_oci::client::ARCHIVE_MEDIATYPE => {
+                            verify_layer_digest(artifact)?;
                             log::debug!(
                                 "<<< writing archive layer and unpacking contents to cache, near {:?}",
                                 cache.manifests_dir()
@@ -85,26 +165,38 @@ impl Source {
                         }
                     }
                 }
-                Ok(Source::Oci)
+                Ok(Source::Oci(OciImage::new(env, layers)))
             }
         }
     }
 
-    pub(crate) async fn to_locked_app(&self, cache: &Cache) -> Result<LockedApp> {
+    pub(crate) async fn to_locked_app(
+        &self,
+        env: &ContainerEnv,
+        cache: &Cache,
+    ) -> Result<LockedApp> {
         let locked_app = match self {
             Source::File(source) => {
-                // TODO: This should be configurable, see https://github.com/deislabs/containerd-wasm-shims/issues/166
-                // TODO: ^^ Move aforementioned issue to this repo
-                let files_mount_strategy = FilesMountStrategy::Direct;
-                spin_loader::from_file(&source, files_mount_strategy, None).await
+                validate_referenced_files(source)?;
+                let files_mount_strategy = files_mount_strategy(env)?;
+                info!(" >>> using files mount strategy {files_mount_strategy:?}");
+                spin_loader::from_file(
+                    &source,
+                    files_mount_strategy,
+                    Some(PathBuf::from(constants::SPIN_CACHE_DIR)),
+                )
+                .await
             }
-            Source::Oci => {
+            Source::Oci(image) => {
                 let working_dir = PathBuf::from("/");
                 let loader = spin_oci::OciLoader::new(working_dir);
 
-                // TODO: what is the best way to get this info? It isn't used only saved in the locked file
-                let reference = "docker.io/library/wasmtest_spin:latest";
-
+                // Spin records the reference as the origin of the application,
+                // which must be a valid reference
+                let reference = image
+                    .reference
+                    .as_deref()
+                    .unwrap_or(constants::SPIN_OCI_IMAGE_REFERENCE_UNKNOWN);
                 loader
                     .load_from_cache(PathBuf::from("/spin.json"), reference, cache)
                     .await
@@ -113,3 +205,398 @@ impl Source {
         Ok(locked_app)
     }
 }
+
+/// Returns the Spin application config layer among the layers of an image.
+pub(crate) fn config_layer(layers: &[WasmLayer]) -> Option<&WasmLayer> {
+    layers.iter().find(|artifact| {
+        matches!(
+            artifact.config.media_type(),
+            MediaType::Other(name) if name == spin_oci::client::SPIN_APPLICATION_MEDIA_TYPE
+        )
+    })
+}
+
+/// Returns the components whose layers are precompiled for an image, as annotated
+/// on its Spin application config layer with
+/// [`constants::SPIN_COMPONENTS_TO_RETAIN_ANNOTATION`].
+pub(crate) fn components_to_retain(config: &WasmLayer) -> Option<&str> {
+    config
+        .config
+        .annotations()
+        .as_ref()?
+        .get(constants::SPIN_COMPONENTS_TO_RETAIN_ANNOTATION)
+        .map(String::as_str)
+}
+
+/// Verifies the Spin application config layer against its detached signature
+/// layer before any layer is written to disk.
+fn verify_signature(verifier: &SignatureVerifier, layers: &[WasmLayer]) -> Result<()> {
+    if !verifier.is_enabled() {
+        return Ok(());
+    }
+    let find_layer = |media_type: &str| {
+        layers.iter().find(|artifact| {
+            matches!(artifact.config.media_type(), MediaType::Other(name) if name == media_type)
+        })
+    };
+    let config = find_layer(spin_oci::client::SPIN_APPLICATION_MEDIA_TYPE)
+        .context("image has no spin application config layer")?;
+    let signature = find_layer(constants::OCI_LAYER_MEDIA_TYPE_SIGNATURE);
+    verifier.verify(
+        &config.layer,
+        config.config.digest(),
+        signature.map(|artifact| artifact.layer.as_slice()),
+    )
+}
+
+/// Reads the files mount strategy of file based applications from
+/// [`constants::SPIN_FILES_MOUNT_STRATEGY_ENV`].
+fn files_mount_strategy(env: &ContainerEnv) -> Result<FilesMountStrategy> {
+    match env.var(constants::SPIN_FILES_MOUNT_STRATEGY_ENV) {
+        None | Some("direct") => Ok(FilesMountStrategy::Direct),
+        Some("copy") => {
+            let dir = env
+                .var(constants::SPIN_FILES_COPY_DIR_ENV)
+                .unwrap_or(constants::SPIN_FILES_COPY_DIR_DEFAULT);
+            Ok(FilesMountStrategy::Copy(PathBuf::from(dir)))
+        }
+        Some(other) => bail!(
+            "invalid value {other:?} for {}: expected direct or copy",
+            constants::SPIN_FILES_MOUNT_STRATEGY_ENV
+        ),
+    }
+}
+
+/// Checks that the local component sources and files referenced by the manifest
+/// exist, so that a misbuilt image fails with every missing path rather than the
+/// first one the loader happens to encounter.
+fn validate_referenced_files(manifest_path: &Path) -> Result<()> {
+    let manifest = spin_manifest::manifest_from_file(manifest_path)
+        .with_context(|| format!("failed to read manifest {}", manifest_path.display()))?;
+    let base = manifest_path.parent().unwrap_or(Path::new("/"));
+    let mut missing = Vec::new();
+    for (id, component) in &manifest.components {
+        let mut referenced = Vec::new();
+        if let ComponentSource::Local(source) = &component.source {
+            referenced.push(base.join(source));
+        }
+        for mount in &component.files {
+            referenced.push(match mount {
+                WasiFilesMount::Pattern(pattern) => base.join(pattern_root(pattern)),
+                WasiFilesMount::Placement { source, .. } => base.join(source),
+            });
+        }
+        missing.extend(
+            referenced
+                .into_iter()
+                .filter(|path| !path.exists())
+                .map(|path| format!("{} (component {id})", path.display())),
+        );
+    }
+    if !missing.is_empty() {
+        bail!(
+            "application references files that do not exist:\n  {}",
+            missing.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Returns the leading part of a files pattern that contains no glob characters.
+fn pattern_root(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| match component {
+            Component::Normal(part) => !part.to_string_lossy().contains(['*', '?', '[', '{']),
+            _ => true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use containerd_shim_wasm::container::{Engine as _, Entrypoint};
+    use oci_spec::image::{Descriptor, Digest, Platform};
+    use p256::{
+        ecdsa::{signature::Signer, Signature, SigningKey},
+        pkcs8::{EncodePublicKey, LineEnding},
+    };
+
+    use sha2::{Digest as _, Sha256};
+
+    use super::*;
+    use crate::engine::SpinEngine;
+
+    struct OciContext {
+        layers: Vec<WasmLayer>,
+        platform: Platform,
+    }
+
+    impl RuntimeContext for OciContext {
+        fn args(&self) -> &[String] {
+            &[]
+        }
+
+        fn envs(&self) -> &[String] {
+            &[]
+        }
+
+        fn entrypoint(&self) -> Entrypoint {
+            Entrypoint {
+                func: "_start".to_string(),
+                name: None,
+                arg0: None,
+                source: containerd_shim_wasm::container::Source::Oci(&self.layers),
+            }
+        }
+
+        fn platform(&self) -> &Platform {
+            &self.platform
+        }
+    }
+
+    fn wasm_layer(layer: Vec<u8>, digest: &str) -> WasmLayer {
+        WasmLayer {
+            layer,
+            config: Descriptor::new(
+                MediaType::Other(constants::OCI_LAYER_MEDIA_TYPE_WASM.to_string()),
+                1024,
+                Digest::from_str(digest).unwrap(),
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_precompiled_wasm_layers() {
+        let wasmtime_engine = wasmtime::Engine::default();
+        let wasm = wat::parse_str("(component)").unwrap();
+        let digest = format!("sha256:{:x}", Sha256::digest(&wasm));
+        let precompiled = wasmtime::component::Component::new(&wasmtime_engine, &wasm)
+            .unwrap()
+            .serialize()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(Some(dir.path().to_path_buf())).await.unwrap();
+
+        // runwasi replaces the layers it precompiled, keeping their descriptors
+        let ctx = OciContext {
+            layers: vec![wasm_layer(precompiled.clone(), &digest)],
+            platform: Platform::default(),
+        };
+        let verifier = SignatureVerifier::default();
+        let source = Source::from_ctx(
+            &ctx,
+            &ContainerEnv::default(),
+            &cache,
+            &wasmtime_engine,
+            &verifier,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(source, Source::Oci(_)));
+        assert_eq!(
+            std::fs::read(cache.wasm_file(&digest).unwrap()).unwrap(),
+            precompiled
+        );
+
+        // Wasm that is not precompiled is still checked against its digest
+        let ctx = OciContext {
+            layers: vec![wasm_layer(b"tampered".to_vec(), &digest)],
+            platform: Platform::default(),
+        };
+        let err = Source::from_ctx(
+            &ctx,
+            &ContainerEnv::default(),
+            &cache,
+            &wasmtime_engine,
+            &verifier,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains(&digest));
+    }
+
+    struct FileContext {
+        platform: Platform,
+    }
+
+    impl RuntimeContext for FileContext {
+        fn args(&self) -> &[String] {
+            &[]
+        }
+
+        fn envs(&self) -> &[String] {
+            &[]
+        }
+
+        fn entrypoint(&self) -> Entrypoint {
+            Entrypoint {
+                func: "_start".to_string(),
+                name: None,
+                arg0: None,
+                source: containerd_shim_wasm::container::Source::File(
+                    constants::SPIN_MANIFEST_FILE_PATH.into(),
+                ),
+            }
+        }
+
+        fn platform(&self) -> &Platform {
+            &self.platform
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_signature_policy_to_file_apps() {
+        let wasmtime_engine = wasmtime::Engine::default();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(Some(dir.path().to_path_buf())).await.unwrap();
+        let ctx = FileContext {
+            platform: Platform::default(),
+        };
+        let from_ctx = |policy: &str| {
+            let env = ContainerEnv::from_iter([(constants::SPIN_SIGNATURE_POLICY_ENV, policy)]);
+            let verifier = SignatureVerifier::default().for_container(&env).unwrap();
+            let cache = &cache;
+            let wasmtime_engine = &wasmtime_engine;
+            let ctx = &ctx;
+            async move { Source::from_ctx(ctx, &env, cache, wasmtime_engine, &verifier).await }
+        };
+
+        assert!(matches!(from_ctx("off").await.unwrap(), Source::File(_)));
+        assert!(matches!(from_ctx("warn").await.unwrap(), Source::File(_)));
+        let err = from_ctx("enforce").await.unwrap_err();
+        assert!(err.to_string().contains("is not signed"));
+    }
+
+    #[test]
+    fn oci_image_manifest_digest_from_reference() {
+        let image = |reference: Option<&str>| OciImage {
+            reference: reference.map(str::to_string),
+            config_digest: None,
+        };
+        let pinned = image(Some("ghcr.io/spinkube/hello:v1@sha256:abc"));
+        assert_eq!(pinned.name(), Some("ghcr.io/spinkube/hello:v1"));
+        assert_eq!(pinned.manifest_digest(), Some("sha256:abc"));
+        let tagged = image(Some("ghcr.io/spinkube/hello:v1"));
+        assert_eq!(tagged.name(), Some("ghcr.io/spinkube/hello:v1"));
+        assert_eq!(tagged.manifest_digest(), None);
+        let unknown = image(None);
+        assert_eq!(unknown.name(), None);
+        assert_eq!(unknown.manifest_digest(), None);
+    }
+
+    #[test]
+    fn verifies_signature_of_advertised_layers() {
+        let config = br#"{"spin_lock_version":1,"components":[],"triggers":[]}"#.to_vec();
+        let key = SigningKey::from_bytes(&[1; 32].into()).unwrap();
+        let signature: Signature = key.sign(&config);
+        let layer = |media_type: &str, layer: Vec<u8>| WasmLayer {
+            layer,
+            config: Descriptor::new(
+                MediaType::Other(media_type.to_string()),
+                1024,
+                Digest::from_str(&format!("sha256:{}", "a".repeat(64))).unwrap(),
+            ),
+        };
+        // runwasi only hands the layers with a supported media type to the shim
+        let layers: Vec<WasmLayer> = [
+            layer(spin_oci::client::SPIN_APPLICATION_MEDIA_TYPE, config),
+            layer(
+                constants::OCI_LAYER_MEDIA_TYPE_SIGNATURE,
+                STANDARD.encode(signature.to_der()).into_bytes(),
+            ),
+        ]
+        .into_iter()
+        .filter(|artifact| {
+            SpinEngine::supported_layers_types()
+                .iter()
+                .any(|media_type| artifact.config.media_type().to_string() == *media_type)
+        })
+        .collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("cosign.pub");
+        let pem = key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        std::fs::write(&key_path, pem).unwrap();
+        let verifier = temp_env::with_vars(
+            [
+                (constants::SPIN_SIGNATURE_POLICY_ENV, Some("enforce")),
+                (constants::SPIN_SIGNATURE_PUBLIC_KEY_ENV, key_path.to_str()),
+            ],
+            SignatureVerifier::from_node_env,
+        );
+        verify_signature(&verifier, &layers).unwrap();
+    }
+
+    #[test]
+    fn files_mount_strategy_from_env() {
+        assert!(matches!(
+            files_mount_strategy(&ContainerEnv::default()).unwrap(),
+            FilesMountStrategy::Direct
+        ));
+        let env = ContainerEnv::from_iter([
+            (constants::SPIN_FILES_MOUNT_STRATEGY_ENV, "copy"),
+            (constants::SPIN_FILES_COPY_DIR_ENV, "/tmp/files"),
+        ]);
+        match files_mount_strategy(&env).unwrap() {
+            FilesMountStrategy::Copy(dir) => assert_eq!(dir, PathBuf::from("/tmp/files")),
+            FilesMountStrategy::Direct => panic!("expected copy strategy"),
+        }
+        let env = ContainerEnv::from_iter([(constants::SPIN_FILES_MOUNT_STRATEGY_ENV, "symlink")]);
+        assert!(files_mount_strategy(&env).is_err());
+    }
+
+    #[test]
+    fn pattern_root_stops_at_globs() {
+        assert_eq!(pattern_root("static/**/*.html"), PathBuf::from("static"));
+        assert_eq!(pattern_root("*.txt"), PathBuf::new());
+        assert_eq!(
+            pattern_root("assets/logo.png"),
+            PathBuf::from("assets/logo.png")
+        );
+    }
+
+    #[test]
+    fn reports_all_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_path = dir.path().join("spin.toml");
+        std::fs::write(
+            &manifest_path,
+            r#"
+            spin_manifest_version = 2
+
+            [application]
+            name = "files"
+
+            [[trigger.http]]
+            route = "/..."
+            component = "hello"
+
+            [component.hello]
+            source = "hello.wasm"
+            files = ["present.txt", "static/**/*", { source = "assets", destination = "/assets" }]
+            "#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("hello.wasm"), b"").unwrap();
+        std::fs::write(dir.path().join("present.txt"), b"").unwrap();
+
+        let err = validate_referenced_files(&manifest_path)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&dir.path().join("static").display().to_string()));
+        assert!(err.contains(&dir.path().join("assets").display().to_string()));
+        assert!(!err.contains("present.txt"));
+        assert!(!err.contains("hello.wasm"));
+
+        std::fs::create_dir(dir.path().join("static")).unwrap();
+        std::fs::create_dir(dir.path().join("assets")).unwrap();
+        validate_referenced_files(&manifest_path).unwrap();
+    }
+}
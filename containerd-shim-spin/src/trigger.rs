@@ -1,10 +1,21 @@
-use std::{collections::HashSet, future::Future, path::Path, pin::Pin};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
+use anyhow::Context;
+use futures::{future::LocalBoxFuture, FutureExt};
 use log::info;
 use spin_app::{locked::LockedApp, App};
+use spin_factors_executor::FactorsExecutor;
 use spin_runtime_factors::{FactorsBuilder, TriggerFactors};
 use spin_trigger::{
-    cli::{FactorsConfig, TriggerAppBuilder, UserProvidedPath},
+    cli::{FactorsConfig, NoCliArgs, RuntimeFactorsBuilder, TriggerAppBuilder, UserProvidedPath},
     loader::ComponentLoader,
     Trigger,
 };
@@ -14,39 +25,159 @@ use trigger_command::CommandTrigger;
 use trigger_mqtt::MqttTrigger;
 use trigger_sqs::SqsTrigger;
 
-use crate::constants::{RUNTIME_CONFIG_PATH, SPIN_TRIGGER_WORKING_DIR};
+use crate::{
+    constants::{
+        SPIN_ADDR_DEFAULT, SPIN_HTTP_LISTEN_ADDR_ENV, SPIN_TRIGGERS_TO_RUN_ENV,
+        SPIN_TRIGGER_RESTART_POLICY_ENV, SPIN_TRIGGER_WORKING_DIR,
+        TRIGGER_RESTART_INITIAL_BACKOFF_SECS, TRIGGER_RESTART_MAX_BACKOFF_SECS,
+    },
+    container_env::ContainerEnv,
+    cron_trigger::CronTrigger,
+    file_variables::FileVariablesProvider,
+    invocations::{Invocations, InvocationsHook},
+    limits::{InstanceLimits, LimitsHook},
+    metrics::{Metrics, MetricsHook},
+    pooling::AllocatorMode,
+    runtime_config::ResolvedRuntimeConfig,
+    server::{self, PortReservation, ServerHandle},
+    utils::{get_tls_config, parse_listen_addrs, ListenAddr},
+};
+
+/// Future that runs a trigger until it exits.
+pub(crate) type TriggerFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>>>>;
+
+/// A constructed trigger.
+pub(crate) struct StartedTrigger {
+    /// Future that runs the trigger until it exits
+    pub(crate) future: TriggerFuture,
+    /// Address the trigger accepts connections on once it runs, if it listens on one
+    pub(crate) listen_addr: Option<SocketAddr>,
+}
+
+tokio::task_local! {
+    /// Environment and state of the container whose trigger is being built, for
+    /// [`ShimFactorsBuilder`] and the triggers of the shim, which Spin constructs
+    /// without any state
+    static TRIGGER_SCOPE: TriggerScope;
+}
+
+#[derive(Clone)]
+struct TriggerScope {
+    env: ContainerEnv,
+    state: AppState,
+}
+
+/// State the shim keeps for the application of a container, shared by its
+/// triggers and the executor hooks of the shim.
+#[derive(Clone)]
+pub(crate) struct AppState {
+    pub(crate) invocations: Arc<Invocations>,
+    pub(crate) limits: Arc<InstanceLimits>,
+    /// Metrics of the application, if they are served
+    pub(crate) metrics: Option<Arc<Metrics>>,
+    pub(crate) runtime_config: Arc<ResolvedRuntimeConfig>,
+}
 
-pub(crate) const HTTP_TRIGGER_TYPE: &str = <HttpTrigger as Trigger<TriggerFactors>>::TYPE;
-pub(crate) const REDIS_TRIGGER_TYPE: &str = <RedisTrigger as Trigger<TriggerFactors>>::TYPE;
-pub(crate) const SQS_TRIGGER_TYPE: &str = <SqsTrigger as Trigger<TriggerFactors>>::TYPE;
-pub(crate) const MQTT_TRIGGER_TYPE: &str = <MqttTrigger as Trigger<TriggerFactors>>::TYPE;
-pub(crate) const COMMAND_TRIGGER_TYPE: &str = <CommandTrigger as Trigger<TriggerFactors>>::TYPE;
+/// Returns the [`AppState`] of the container whose trigger is being built.
+pub(crate) fn app_state() -> anyhow::Result<AppState> {
+    TRIGGER_SCOPE
+        .try_with(|scope| scope.state.clone())
+        .context("trigger built outside of a container environment")
+}
 
-/// Run the trigger with the given CLI args, [`App`] and [`ComponentLoader`].
+/// Run the trigger with the given CLI args and [`App`], using the container
+/// environment, [`AppState`], [`ComponentLoader`] and instance [`AllocatorMode`]
+/// of `args`.
 pub(crate) async fn run<T>(
     cli_args: T::CliArgs,
     app: App,
-    loader: &ComponentLoader,
-) -> anyhow::Result<Pin<Box<dyn Future<Output = anyhow::Result<()>>>>>
+    args: &TriggerArgs<'_>,
+) -> anyhow::Result<TriggerFuture>
 where
     T: Trigger<TriggerFactors> + 'static,
 {
-    info!(" >>> running {} trigger", T::TYPE);
-    let trigger = T::new(cli_args, &app)?;
-    let builder: TriggerAppBuilder<_, FactorsBuilder> = TriggerAppBuilder::new(trigger);
+    info!(trigger = T::TYPE; " >>> running {} trigger", T::TYPE);
+    let scope = TriggerScope {
+        env: args.env.clone(),
+        state: args.state.clone(),
+    };
+    let trigger = TRIGGER_SCOPE.sync_scope(scope.clone(), || T::new(cli_args, &app))?;
+    let mut builder: TriggerAppBuilder<_, ShimFactorsBuilder> = TriggerAppBuilder::new(trigger);
+    args.allocator.configure(builder.engine_config());
 
-    let future = builder
-        .run(app, factors_config(), Default::default(), loader)
+    let future = TRIGGER_SCOPE
+        .scope(
+            scope,
+            builder.run(
+                app,
+                factors_config(&args.state.runtime_config),
+                Default::default(),
+                args.loader,
+            ),
+        )
         .await?;
     Ok(Box::pin(future))
 }
 
+/// Builds the Spin runtime factors like [`FactorsBuilder`], additionally
+/// registering the variable providers and executor hooks of the shim.
+struct ShimFactorsBuilder;
+
+impl RuntimeFactorsBuilder for ShimFactorsBuilder {
+    type CliArgs = <FactorsBuilder as RuntimeFactorsBuilder>::CliArgs;
+    type Factors = <FactorsBuilder as RuntimeFactorsBuilder>::Factors;
+    type RuntimeConfig = <FactorsBuilder as RuntimeFactorsBuilder>::RuntimeConfig;
+
+    fn build(
+        config: &FactorsConfig,
+        args: &Self::CliArgs,
+    ) -> anyhow::Result<(Self::Factors, Self::RuntimeConfig)> {
+        let TriggerScope { env, state } = TRIGGER_SCOPE
+            .try_with(TriggerScope::clone)
+            .context("trigger built outside of a container environment")?;
+        let resolved = state.runtime_config.write()?;
+        let (factors, mut runtime_config) = FactorsBuilder::build(config, args)?;
+        drop(resolved);
+        let providers = &mut runtime_config
+            .runtime_config
+            .variables
+            .get_or_insert_with(Default::default)
+            .providers;
+        // The container environment takes precedence over the configured providers,
+        // and mounted variable files over both
+        providers.insert(0, Box::new(env.clone()));
+        if let Some(provider) = FileVariablesProvider::from_env(&env)? {
+            providers.insert(0, Box::new(provider));
+        }
+        Ok((factors, runtime_config))
+    }
+
+    fn configure_app<U: Send + 'static>(
+        executor: &mut FactorsExecutor<Self::Factors, U>,
+        runtime_config: &Self::RuntimeConfig,
+        config: &FactorsConfig,
+        args: &Self::CliArgs,
+    ) -> anyhow::Result<()> {
+        FactorsBuilder::configure_app(executor, runtime_config, config, args)?;
+        let state = app_state()?;
+        executor.add_hooks(InvocationsHook::new(state.invocations));
+        if !state.limits.is_empty() {
+            executor.add_hooks(LimitsHook(state.limits));
+        }
+        if let Some(metrics) = state.metrics {
+            executor.add_hooks(MetricsHook(metrics));
+        }
+        Ok(())
+    }
+}
+
 /// Configuration for the factors.
-fn factors_config() -> FactorsConfig {
-    // Load in runtime config if one exists at expected location
-    let runtime_config_file = Path::new(RUNTIME_CONFIG_PATH)
-        .exists()
-        .then(|| RUNTIME_CONFIG_PATH.into());
+fn factors_config(runtime_config: &ResolvedRuntimeConfig) -> FactorsConfig {
+    // The runtime config resolved from the one and its fragments at the expected
+    // locations, if any exist
+    let runtime_config_file = runtime_config
+        .path()
+        .map(|path| path.to_string_lossy().into_owned());
     // Configure the application state directory path. This is used in the default
     // locations for logs, key value stores, etc.
     FactorsConfig {
@@ -62,39 +193,467 @@ fn factors_config() -> FactorsConfig {
     }
 }
 
-/// get the supported trigger types from the `LockedApp`.
-///
-/// this function filters the trigger types to only return the ones that are currently supported.
-/// If an unsupported trigger type is found, it returns an error indicating which trigger type is unsupported.
+/// Arguments available to triggers while they are constructed.
+pub(crate) struct TriggerArgs<'a> {
+    /// Arguments of the container, passed to the guest by the command trigger
+    pub(crate) guest_args: &'a [String],
+    pub(crate) env: &'a ContainerEnv,
+    pub(crate) loader: &'a ComponentLoader,
+    pub(crate) allocator: &'a AllocatorMode,
+    pub(crate) state: &'a AppState,
+}
+
+/// Builds the CLI args of a trigger from the container environment.
+pub(crate) type CliArgsBuilder<T> =
+    fn(&TriggerArgs<'_>) -> anyhow::Result<<T as Trigger<TriggerFactors>>::CliArgs>;
+
+/// Builds the [`TriggerSetup`] of a trigger from the container environment.
+pub(crate) type SetupBuilder<T> =
+    fn(&TriggerArgs<'_>) -> anyhow::Result<TriggerSetup<<T as Trigger<TriggerFactors>>::CliArgs>>;
+
+/// CLI args of a trigger, along with the servers the shim runs next to the
+/// trigger for as long as it runs and the address the trigger listens on.
+pub(crate) struct TriggerSetup<C> {
+    pub(crate) cli_args: C,
+    pub(crate) servers: Vec<ServerHandle>,
+    pub(crate) listen_addr: Option<SocketAddr>,
+    /// Port reserved for the trigger to listen on, held while it runs
+    pub(crate) reserved_port: Option<PortReservation>,
+}
+
+trait TriggerRunner {
+    fn start<'a>(
+        &'a self,
+        args: &'a TriggerArgs<'a>,
+        app: App,
+    ) -> LocalBoxFuture<'a, anyhow::Result<StartedTrigger>>;
+}
+
+struct Registration<T: Trigger<TriggerFactors>> {
+    setup: Box<dyn Fn(&TriggerArgs<'_>) -> anyhow::Result<TriggerSetup<T::CliArgs>>>,
+}
+
+impl<T: Trigger<TriggerFactors> + 'static> TriggerRunner for Registration<T> {
+    fn start<'a>(
+        &'a self,
+        args: &'a TriggerArgs<'a>,
+        app: App,
+    ) -> LocalBoxFuture<'a, anyhow::Result<StartedTrigger>> {
+        async move {
+            let TriggerSetup {
+                cli_args,
+                servers,
+                listen_addr,
+                reserved_port,
+            } = (self.setup)(args)?;
+            let future = run::<T>(cli_args, app, args).await?;
+            Ok(StartedTrigger {
+                future: Box::pin(async move {
+                    let _servers = servers;
+                    let _reserved_port = reserved_port;
+                    future.await
+                }),
+                listen_addr,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+/// Registry of the trigger types the shim can run.
 ///
-/// supported trigger types include:
-/// - redis
-/// - http
-/// - sqs
-/// - mqtt
-/// - command
+/// Supporting a new trigger type only takes registering its [`Trigger`]
+/// implementation together with a [`CliArgsBuilder`] in [`TriggerRegistry::default`].
+pub(crate) struct TriggerRegistry {
+    triggers: BTreeMap<&'static str, Box<dyn TriggerRunner>>,
+}
+
+impl Default for TriggerRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            triggers: BTreeMap::new(),
+        };
+        registry
+            .register_with_setup::<HttpTrigger>(http_setup)
+            .register::<RedisTrigger>(|_| Ok(NoCliArgs))
+            .register::<SqsTrigger>(|_| Ok(NoCliArgs))
+            .register::<CommandTrigger>(|args| {
+                Ok(trigger_command::CliArgs {
+                    guest_args: args.guest_args.to_vec(),
+                })
+            })
+            .register::<MqttTrigger>(|_| Ok(trigger_mqtt::CliArgs { test: false }))
+            .register::<CronTrigger>(|_| Ok(NoCliArgs));
+        registry
+    }
+}
+
+impl TriggerRegistry {
+    /// Registers the trigger `T` under its trigger type.
+    pub(crate) fn register<T>(&mut self, cli_args: CliArgsBuilder<T>) -> &mut Self
+    where
+        T: Trigger<TriggerFactors> + 'static,
+    {
+        let setup = move |args: &TriggerArgs<'_>| -> anyhow::Result<_> {
+            Ok(TriggerSetup {
+                cli_args: cli_args(args)?,
+                servers: Vec::new(),
+                listen_addr: None,
+                reserved_port: None,
+            })
+        };
+        self.triggers.insert(
+            T::TYPE,
+            Box::new(Registration::<T> {
+                setup: Box::new(setup),
+            }),
+        );
+        self
+    }
+
+    /// Registers the trigger `T` under its trigger type, with a setup that also
+    /// starts servers next to the trigger.
+    pub(crate) fn register_with_setup<T>(&mut self, setup: SetupBuilder<T>) -> &mut Self
+    where
+        T: Trigger<TriggerFactors> + 'static,
+    {
+        self.triggers.insert(
+            T::TYPE,
+            Box::new(Registration::<T> {
+                setup: Box::new(setup),
+            }),
+        );
+        self
+    }
+
+    /// get the supported trigger types from the `LockedApp`.
+    ///
+    /// If `triggers_to_run` is set, as a comma separated list of trigger types per
+    /// [`SPIN_TRIGGERS_TO_RUN_ENV`], only those trigger types are returned. They must
+    /// all be used by the application.
+    ///
+    /// If an unsupported trigger type is found, it returns an error indicating which trigger type is unsupported.
+    ///
+    /// Note: this function returns a `HashSet` of supported trigger types. Duplicates are removed.
+    pub(crate) fn supported_triggers(
+        &self,
+        locked_app: &LockedApp,
+        triggers_to_run: Option<&str>,
+    ) -> anyhow::Result<HashSet<String>> {
+        let app_triggers = locked_app
+            .triggers
+            .iter()
+            .map(|trigger| trigger.trigger_type.clone())
+            .collect::<HashSet<_>>();
+        let trigger_types = match triggers_to_run {
+            Some(spec) => select_triggers(spec, &app_triggers)?,
+            None => app_triggers,
+        };
+        trigger_types
+            .into_iter()
+            .map(|trigger_type| {
+                if !self.triggers.contains_key(trigger_type.as_str()) {
+                    Err(anyhow::anyhow!(
+                        "Only {} triggers are currently supported. Found unsupported trigger: {:?}",
+                        self.triggers.keys().copied().collect::<Vec<_>>().join(", "),
+                        trigger_type
+                    ))
+                } else {
+                    Ok(trigger_type)
+                }
+            })
+            .collect()
+    }
+
+    /// Constructs the trigger of the given type.
+    pub(crate) async fn start(
+        &self,
+        trigger_type: &str,
+        args: &TriggerArgs<'_>,
+        app: App,
+    ) -> anyhow::Result<StartedTrigger> {
+        let runner = self
+            .triggers
+            .get(trigger_type)
+            .with_context(|| format!("trigger type {trigger_type:?} is not supported"))?;
+        runner.start(args, app).await
+    }
+}
+
+/// Selects the trigger types listed in the value of [`SPIN_TRIGGERS_TO_RUN_ENV`]
+/// among the ones the application uses.
+fn select_triggers(spec: &str, app_triggers: &HashSet<String>) -> anyhow::Result<HashSet<String>> {
+    let mut selected = HashSet::new();
+    for trigger_type in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if !app_triggers.contains(trigger_type) {
+            let mut available = app_triggers.iter().cloned().collect::<Vec<_>>();
+            available.sort();
+            anyhow::bail!(
+                "{SPIN_TRIGGERS_TO_RUN_ENV} selects trigger type {trigger_type:?}, which the application does not use: expected one of {}",
+                available.join(", ")
+            );
+        }
+        selected.insert(trigger_type.to_string());
+    }
+    if selected.is_empty() {
+        anyhow::bail!("{SPIN_TRIGGERS_TO_RUN_ENV} is set but selects no trigger types to run");
+    }
+    Ok(selected)
+}
+
+/// Sets up the HTTP trigger to listen on the first TCP address of
+/// [`SPIN_HTTP_LISTEN_ADDR_ENV`], forwarding connections from the other addresses.
+fn http_setup(args: &TriggerArgs<'_>) -> anyhow::Result<TriggerSetup<spin_trigger_http::CliArgs>> {
+    let spec = args
+        .env
+        .var(SPIN_HTTP_LISTEN_ADDR_ENV)
+        .unwrap_or(SPIN_ADDR_DEFAULT);
+    let mut addrs = parse_listen_addrs(spec)?;
+    let primary = addrs.iter().find_map(|addr| match addr {
+        ListenAddr::Tcp(addr) => Some(*addr),
+        ListenAddr::Unix(_) => None,
+    });
+    let (address, reserved_port) = match primary {
+        Some(address) => {
+            addrs.retain(|addr| *addr != ListenAddr::Tcp(address));
+            (address, None)
+        }
+        // Only Unix domain sockets: the trigger listens on a loopback port
+        // reserved until it stops
+        None => {
+            let reservation = server::reserve_loopback_port()
+                .context("failed to find a free port for the HTTP trigger")?;
+            (reservation.addr, Some(reservation))
+        }
+    };
+    let target = match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, address.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, address.port()).into(),
+        _ => address,
+    };
+    let servers = addrs
+        .iter()
+        .map(|addr| server::forward(addr, target))
+        .collect::<anyhow::Result<_>>()?;
+
+    let (tls_cert, tls_key) = get_tls_config(args.env)
+        .context("failed to configure TLS for the HTTP trigger")?
+        .unzip();
+    if let Some(cert) = &tls_cert {
+        info!(" >>> serving HTTPS with certificate {}", cert.display());
+    }
+    Ok(TriggerSetup {
+        cli_args: spin_trigger_http::CliArgs {
+            address,
+            tls_cert,
+            tls_key,
+        },
+        servers,
+        listen_addr: Some(target),
+        reserved_port,
+    })
+}
+
+/// What the shim does when a trigger of the application exits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum RestartPolicy {
+    /// Stop the whole application
+    #[default]
+    ExitAll,
+    /// Restart the trigger after an exponential backoff
+    Restart,
+    /// Keep the other triggers running
+    Ignore,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "exit-all" => Ok(Self::ExitAll),
+            "restart" => Ok(Self::Restart),
+            "ignore" => Ok(Self::Ignore),
+            other => Err(anyhow::anyhow!(
+                "unknown trigger restart policy {other:?}: expected one of exit-all, restart or ignore"
+            )),
+        }
+    }
+}
+
+/// Parses the restart policy of each of the running `trigger_types` from the value
+/// of [`SPIN_TRIGGER_RESTART_POLICY_ENV`]. Triggers without a policy exit the application.
 ///
-/// Note: this function returns a `HashSet` of supported trigger types. Duplicates are removed.
-pub(crate) fn get_supported_triggers(locked_app: &LockedApp) -> anyhow::Result<HashSet<String>> {
-    let supported_triggers: HashSet<&str> = HashSet::from([
-        HTTP_TRIGGER_TYPE,
-        REDIS_TRIGGER_TYPE,
-        SQS_TRIGGER_TYPE,
-        COMMAND_TRIGGER_TYPE,
-        MQTT_TRIGGER_TYPE,
-    ]);
-
-    locked_app.triggers.iter()
-        .map(|trigger| {
-            let trigger_type = &trigger.trigger_type;
-            if !supported_triggers.contains(trigger_type.as_str()) {
-                Err(anyhow::anyhow!(
-                    "Only Http, Redis, MQTT, SQS, and Command triggers are currently supported. Found unsupported trigger: {:?}",
-                    trigger_type
-                ))
-            } else {
-                Ok(trigger_type.clone())
+/// Policies may be configured for any of the `app_trigger_types`, so that the same
+/// configuration can be used whichever triggers [`SPIN_TRIGGERS_TO_RUN_ENV`] selects;
+/// the policies of triggers that do not run are ignored.
+pub(crate) fn parse_restart_policies(
+    spec: &str,
+    app_trigger_types: &HashSet<String>,
+    trigger_types: &HashSet<String>,
+) -> anyhow::Result<HashMap<String, RestartPolicy>> {
+    let mut policies = HashMap::new();
+    for entry in spec.split(',').filter(|s| !s.trim().is_empty()) {
+        match entry.split_once('=') {
+            Some((trigger_type, policy)) => {
+                let trigger_type = trigger_type.trim();
+                if !app_trigger_types.contains(trigger_type) {
+                    anyhow::bail!(
+                        "{SPIN_TRIGGER_RESTART_POLICY_ENV} configures trigger type {trigger_type:?}, which the application does not use"
+                    );
+                }
+                let policy = policy.parse()?;
+                if trigger_types.contains(trigger_type) {
+                    policies.insert(trigger_type.to_string(), policy);
+                }
+            }
+            None => {
+                let policy: RestartPolicy = entry.parse()?;
+                for trigger_type in trigger_types {
+                    policies.insert(trigger_type.clone(), policy);
+                }
             }
-        })
-        .collect::<anyhow::Result<HashSet<_>>>()
+        }
+    }
+    Ok(policies)
+}
+
+/// Delay before the given restart of a trigger, starting at 1.
+pub(crate) fn restart_backoff(restart: u32) -> Duration {
+    let secs = TRIGGER_RESTART_INITIAL_BACKOFF_SECS
+        .saturating_mul(1 << restart.saturating_sub(1).min(16))
+        .min(TRIGGER_RESTART_MAX_BACKOFF_SECS);
+    Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_rejects_unknown_triggers() {
+        let app_json = r#"
+        {
+            "spin_lock_version": 1,
+            "components": [],
+            "triggers": [
+                { "id": "t1", "trigger_type": "http", "trigger_config": {} },
+                { "id": "t2", "trigger_type": "cron", "trigger_config": {} },
+                { "id": "t3", "trigger_type": "http", "trigger_config": {} }
+            ]
+        }"#;
+        let locked_app = LockedApp::from_json(app_json.as_bytes()).unwrap();
+        let registry = TriggerRegistry::default();
+        assert_eq!(
+            registry.supported_triggers(&locked_app, None).unwrap(),
+            HashSet::from(["http".to_string(), "cron".to_string()])
+        );
+
+        let app_json = r#"
+        {
+            "spin_lock_version": 1,
+            "components": [],
+            "triggers": [{ "id": "t1", "trigger_type": "carrier-pigeon", "trigger_config": {} }]
+        }"#;
+        let locked_app = LockedApp::from_json(app_json.as_bytes()).unwrap();
+        let err = registry.supported_triggers(&locked_app, None).unwrap_err();
+        assert!(err.to_string().contains("carrier-pigeon"));
+    }
+
+    #[test]
+    fn registry_selects_triggers_to_run() {
+        let app_json = r#"
+        {
+            "spin_lock_version": 1,
+            "components": [],
+            "triggers": [
+                { "id": "t1", "trigger_type": "http", "trigger_config": {} },
+                { "id": "t2", "trigger_type": "redis", "trigger_config": {} },
+                { "id": "t3", "trigger_type": "mqtt", "trigger_config": {} }
+            ]
+        }"#;
+        let locked_app = LockedApp::from_json(app_json.as_bytes()).unwrap();
+        let registry = TriggerRegistry::default();
+        assert_eq!(
+            registry
+                .supported_triggers(&locked_app, Some("http"))
+                .unwrap(),
+            HashSet::from(["http".to_string()])
+        );
+        assert_eq!(
+            registry
+                .supported_triggers(&locked_app, Some(" redis, mqtt ,"))
+                .unwrap(),
+            HashSet::from(["redis".to_string(), "mqtt".to_string()])
+        );
+
+        let err = registry
+            .supported_triggers(&locked_app, Some("http,cron"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("\"cron\""), "{err}");
+        assert!(err.contains("http, mqtt, redis"), "{err}");
+
+        let err = registry
+            .supported_triggers(&locked_app, Some(" , "))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("selects no trigger types"), "{err}");
+    }
+
+    #[test]
+    fn can_parse_restart_policies() {
+        let trigger_types = HashSet::from(["http".to_string(), "redis".to_string()]);
+        let parse = |spec| parse_restart_policies(spec, &trigger_types, &trigger_types);
+
+        let policies = parse("restart").unwrap();
+        assert_eq!(policies["http"], RestartPolicy::Restart);
+        assert_eq!(policies["redis"], RestartPolicy::Restart);
+
+        let policies = parse("ignore,redis=restart").unwrap();
+        assert_eq!(policies["http"], RestartPolicy::Ignore);
+        assert_eq!(policies["redis"], RestartPolicy::Restart);
+
+        let policies = parse("redis=restart").unwrap();
+        assert!(!policies.contains_key("http"));
+
+        assert!(parse("mqtt=restart").is_err());
+        assert!(parse("redis=sometimes").is_err());
+    }
+
+    #[test]
+    fn ignores_restart_policies_of_triggers_not_selected() {
+        let app_trigger_types = HashSet::from(["http".to_string(), "redis".to_string()]);
+        let trigger_types = HashSet::from(["http".to_string()]);
+
+        let policies = parse_restart_policies(
+            "http=ignore,redis=restart",
+            &app_trigger_types,
+            &trigger_types,
+        )
+        .unwrap();
+        assert_eq!(
+            policies,
+            HashMap::from([("http".to_string(), RestartPolicy::Ignore)])
+        );
+        let policies =
+            parse_restart_policies("restart", &app_trigger_types, &trigger_types).unwrap();
+        assert!(!policies.contains_key("redis"));
+
+        assert!(
+            parse_restart_policies("mqtt=restart", &app_trigger_types, &trigger_types).is_err()
+        );
+        assert!(
+            parse_restart_policies("redis=sometimes", &app_trigger_types, &trigger_types).is_err()
+        );
+    }
+
+    #[test]
+    fn restart_backoff_is_exponential_and_bounded() {
+        assert_eq!(restart_backoff(1), Duration::from_secs(1));
+        assert_eq!(restart_backoff(2), Duration::from_secs(2));
+        assert_eq!(restart_backoff(4), Duration::from_secs(8));
+        assert_eq!(restart_backoff(10), Duration::from_secs(60));
+        assert_eq!(restart_backoff(u32::MAX), Duration::from_secs(60));
+    }
 }